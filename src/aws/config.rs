@@ -0,0 +1,209 @@
+use dirs;
+use error::ErrorKind;
+use failure::{Error, ResultExt};
+use std::env;
+use std::env::var as env_var;
+use std::path::Path;
+use std::path::PathBuf;
+use try_from::TryFrom;
+
+use aws::file;
+use aws::ini::Ini;
+
+/// Settings oktaws can write to the `[profile <name>]` sections of `~/.aws/config`
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ProfileConfig {
+    pub region: Option<String>,
+    pub output: Option<String>,
+    /// Whether the AWS CLI should call `oktaws credential-process` for credentials
+    pub credential_process: Option<bool>,
+}
+
+impl ProfileConfig {
+    /// These settings, falling back to `defaults` for the missing ones
+    pub fn or(self, defaults: &ProfileConfig) -> ProfileConfig {
+        ProfileConfig {
+            region: self.region.or_else(|| defaults.region.clone()),
+            output: self.output.or_else(|| defaults.output.clone()),
+            credential_process: self.credential_process.or(defaults.credential_process),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigStore {
+    path: PathBuf,
+    ini: Ini,
+    changes: Vec<(String, ProfileConfig)>,
+}
+
+impl ConfigStore {
+    pub fn new() -> Result<ConfigStore, Error> {
+        let path = match env_var("AWS_CONFIG_FILE") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => ConfigStore::default_config_location()?,
+        };
+
+        ConfigStore::try_from(path.as_path())
+            .context(ErrorKind::ConfigFile)
+            .map_err(|e| e.into())
+    }
+
+    pub fn has_profile(&self, profile: &str) -> bool {
+        self.ini.has_section(&section_name(profile))
+    }
+
+    pub fn get_region(&self, profile: &str) -> Option<&str> {
+        self.ini.get(&section_name(profile), "region")
+    }
+
+    pub fn set_profile(&mut self, name: String, config: ProfileConfig) -> Result<(), Error> {
+        set_config(&mut self.ini, &name, &config)?;
+        self.changes.push((name, config));
+
+        Ok(())
+    }
+
+    pub fn save(self) -> Result<(), Error> {
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+
+        info!("Saving AWS config");
+
+        let changes = self.changes;
+        file::update(&self.path, |ini| {
+            for (name, config) in &changes {
+                set_config(ini, name, config)?;
+            }
+
+            Ok(())
+        })
+        .context(ErrorKind::ConfigFile)
+        .map_err(|e| e.into())
+    }
+
+    fn default_config_location() -> Result<PathBuf, Error> {
+        match dirs::home_dir() {
+            Some(home_dir) => Ok(home_dir.join(".aws").join("config")),
+            None => bail!("The environment variable HOME must be set."),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Path> for ConfigStore {
+    type Err = Error;
+
+    fn try_from(path: &'a Path) -> Result<Self, Self::Err> {
+        Ok(ConfigStore {
+            path: path.to_path_buf(),
+            ini: file::read(path)?,
+            changes: Vec::new(),
+        })
+    }
+}
+
+/// Profiles other than the default one are prefixed in the config file
+fn section_name(profile: &str) -> String {
+    if profile == "default" {
+        profile.to_owned()
+    } else {
+        format!("profile {}", profile)
+    }
+}
+
+fn set_config(ini: &mut Ini, name: &str, config: &ProfileConfig) -> Result<(), Error> {
+    let section = section_name(name);
+
+    if let Some(ref region) = config.region {
+        ini.set(&section, "region", region);
+    }
+
+    if let Some(ref output) = config.output {
+        ini.set(&section, "output", output);
+    }
+
+    match config.credential_process {
+        Some(true) => ini.set(&section, "credential_process", &credential_process(name)?),
+        Some(false) => {
+            ini.remove(&section, "credential_process");
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+fn credential_process(profile: &str) -> Result<String, Error> {
+    let exe = env::current_exe()?;
+    let exe = exe.to_string_lossy();
+
+    if exe.contains(char::is_whitespace) {
+        Ok(format!("\"{}\" credential-process {}", exe, profile))
+    } else {
+        Ok(format!("{} credential-process {}", exe, profile))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn set_profile_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config");
+        fs::write(
+            &path,
+            "[default]\nregion = us-east-1\n\n[profile example]\noutput = text\n",
+        )
+        .unwrap();
+
+        let mut config_store = ConfigStore::try_from(path.as_path()).unwrap();
+        assert_eq!(config_store.get_region("default"), Some("us-east-1"));
+        assert_eq!(config_store.get_region("example"), None);
+
+        config_store
+            .set_profile(
+                String::from("example"),
+                ProfileConfig {
+                    region: Some(String::from("eu-west-1")),
+                    output: None,
+                    credential_process: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(config_store.get_region("example"), Some("eu-west-1"));
+
+        config_store.save().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[default]\nregion = us-east-1\n\n[profile example]\noutput = text\nregion = eu-west-1\n"
+        );
+    }
+
+    #[test]
+    fn profile_defaults() {
+        let defaults = ProfileConfig {
+            region: Some(String::from("us-east-1")),
+            output: Some(String::from("json")),
+            credential_process: None,
+        };
+        let config = ProfileConfig {
+            region: Some(String::from("eu-west-1")),
+            ..ProfileConfig::default()
+        };
+
+        assert_eq!(
+            config.or(&defaults),
+            ProfileConfig {
+                region: Some(String::from("eu-west-1")),
+                output: Some(String::from("json")),
+                credential_process: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Duration, Utc};
+use rusoto_sts::Credentials;
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json;
+
+use aws::credentials;
+
+/// The document the AWS CLI and SDKs expect from a `credential_process` command
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProcessCredentials {
+    pub version: u8,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+    pub expiration: String,
+}
+
+impl From<Credentials> for ProcessCredentials {
+    fn from(creds: Credentials) -> Self {
+        ProcessCredentials {
+            version: 1,
+            access_key_id: creds.access_key_id,
+            secret_access_key: creds.secret_access_key,
+            session_token: creds.session_token,
+            expiration: creds.expiration,
+        }
+    }
+}
+
+impl ProcessCredentials {
+    pub fn expiration(&self) -> Option<DateTime<Utc>> {
+        credentials::parse_expiration(&self.expiration)
+    }
+
+    /// Whether these credentials will still work for at least `margin`
+    pub fn is_valid_for(&self, margin: Duration) -> bool {
+        credentials::is_valid_for(self.expiration(), margin)
+    }
+}
+
+impl fmt::Display for ProcessCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let json = serde_json::to_string_pretty(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", json)
+    }
+}
+
+impl FromStr for ProcessCredentials {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_process_credentials() {
+        let credentials = ProcessCredentials::from(Credentials {
+            access_key_id: String::from("ACCESS_KEY"),
+            secret_access_key: String::from("SECRET_ACCESS_KEY"),
+            session_token: String::from("SESSION_TOKEN"),
+            expiration: String::from("2015-11-03T10:15:57Z"),
+        });
+
+        let json: serde_json::Value = serde_json::from_str(&credentials.to_string()).unwrap();
+
+        assert_eq!(json["Version"], 1);
+        assert_eq!(json["AccessKeyId"], "ACCESS_KEY");
+        assert_eq!(json["SecretAccessKey"], "SECRET_ACCESS_KEY");
+        assert_eq!(json["SessionToken"], "SESSION_TOKEN");
+        assert_eq!(json["Expiration"], "2015-11-03T10:15:57Z");
+
+        assert_eq!(
+            credentials
+                .to_string()
+                .parse::<ProcessCredentials>()
+                .unwrap(),
+            credentials
+        );
+        assert!(!credentials.is_valid_for(Duration::zero()));
+    }
+}
@@ -1,33 +1,61 @@
+use chrono::{DateTime, Duration, Utc};
 use dirs;
-use failure::Error;
-use path_abs::PathFile;
+use error::ErrorKind;
+use failure::{Error, ResultExt};
 use rusoto_sts::Credentials;
-use serde_ini;
-use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::env::var as env_var;
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::{Seek, SeekFrom};
 use std::path::Path;
 use std::path::PathBuf;
 use std::str;
 use try_from::{TryFrom, TryInto};
 
+use aws::file;
+use aws::ini::Ini;
+use aws::role::RoleCredentials;
+
 #[derive(Debug)]
 pub struct CredentialsStore {
-    file: File,
-    // `BTreeMap`s are sorted
-    credentials: BTreeMap<String, ProfileCredentials>,
+    path: PathBuf,
+    // Edited in place, so that whatever oktaws doesn't manage is left as it was
+    ini: Ini,
+    // Applied again when saving, as another oktaws may have changed the file in the meantime
+    changes: Vec<Change>,
+}
+
+#[derive(Debug)]
+enum Change {
+    Set(String, ProfileCredentials),
+    // Only done if the profile still has the credentials it was removed with
+    Remove(String, ProfileCredentials),
 }
 
 impl CredentialsStore {
     pub fn new() -> Result<CredentialsStore, Error> {
-        match env_var("AWS_SHARED_CREDENTIALS_FILE") {
+        let path = match env_var("AWS_SHARED_CREDENTIALS_FILE") {
             Ok(path) => PathBuf::from(path),
             Err(_) => CredentialsStore::default_profile_location()?,
-        }
-        .try_into()
+        };
+
+        CredentialsStore::try_from(path)
+            .context(ErrorKind::CredentialsFile)
+            .map_err(|e| e.into())
+    }
+
+    pub fn get_profile(&self, name: &str) -> Option<ProfileCredentials> {
+        profile_credentials(&self.ini, name)
+    }
+
+    /// Every profile with credentials, sorted by name
+    pub fn profiles(&self) -> BTreeMap<String, ProfileCredentials> {
+        self.ini
+            .sections()
+            .into_iter()
+            .filter_map(|name| {
+                self.get_profile(name)
+                    .map(|credentials| (name.to_owned(), credentials))
+            })
+            .collect()
     }
 
     pub fn set_profile<T: Into<ProfileCredentials>>(
@@ -35,28 +63,51 @@ impl CredentialsStore {
         name: String,
         creds: T,
     ) -> Result<(), Error> {
-        match self.credentials.entry(name) {
-            Entry::Occupied(mut entry) => match *entry.get() {
-                ProfileCredentials::Sts { .. } => {
-                    entry.insert(creds.into());
-                }
-                ProfileCredentials::Iam { .. } => {
-                    bail!(
-                        "Profile '{}' does not contain STS credentials. Ignoring",
-                        entry.key()
-                    );
-                }
-            },
-            Entry::Vacant(entry) => {
-                entry.insert(creds.into());
-            }
-        }
+        let creds = creds.into();
+        set_credentials(&mut self.ini, &name, creds.clone())?;
+        self.changes.push(Change::Set(name, creds));
+
+        Ok(())
+    }
+
+    /// Removes the STS credentials of a profile, and its section once nothing else is left in it
+    pub fn remove_profile(&mut self, name: String) -> Result<(), Error> {
+        let creds = match profile_credentials(&self.ini, &name) {
+            Some(creds) => creds,
+            None => bail!("Profile '{}' has no credentials", name),
+        };
+
+        remove_credentials(&mut self.ini, &name)?;
+        self.changes.push(Change::Remove(name, creds));
+
         Ok(())
     }
 
     pub fn save(self) -> Result<(), Error> {
         info!("Saving AWS credentials");
-        serde_ini::ser::to_writer(self.file, &self.credentials).map_err(|e| e.into())
+
+        let changes = self.changes;
+        file::update(&self.path, |ini| {
+            for change in changes {
+                match change {
+                    Change::Set(name, creds) => set_credentials(ini, &name, creds)?,
+                    Change::Remove(name, creds) => {
+                        if profile_credentials(ini, &name) == Some(creds) {
+                            remove_credentials(ini, &name)?;
+                        } else {
+                            info!(
+                                "Credentials for {} changed since they were read, keeping them",
+                                name
+                            );
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        })
+        .context(ErrorKind::CredentialsFile)
+        .map_err(|e| e.into())
     }
 
     fn default_profile_location() -> Result<PathBuf, Error> {
@@ -79,47 +130,123 @@ impl<'a, T: ?Sized + AsRef<Path>> TryFrom<&'a T> for CredentialsStore {
     type Err = Error;
 
     fn try_from(file_path: &'a T) -> Result<Self, Self::Err> {
-        PathFile::create(&file_path)?.try_into()
+        Ok(CredentialsStore {
+            path: file_path.as_ref().to_path_buf(),
+            ini: file::read(file_path.as_ref())?,
+            changes: Vec::new(),
+        })
     }
 }
 
-impl TryFrom<PathFile> for CredentialsStore {
-    type Err = Error;
+fn set_credentials(ini: &mut Ini, name: &str, creds: ProfileCredentials) -> Result<(), Error> {
+    if let Some(ProfileCredentials::Iam { .. }) = profile_credentials(ini, name) {
+        bail!(
+            "Profile '{}' does not contain STS credentials. Ignoring",
+            name
+        );
+    }
+
+    match creds {
+        ProfileCredentials::Sts {
+            access_key_id,
+            secret_access_key,
+            session_token,
+            expiration,
+            role_arn,
+        } => {
+            ini.set(name, "aws_access_key_id", &access_key_id);
+            ini.set(name, "aws_secret_access_key", &secret_access_key);
+            ini.set(name, "aws_session_token", &session_token);
+
+            match expiration {
+                Some(expiration) => ini.set(name, "aws_expiration", &expiration),
+                None => {
+                    ini.remove(name, "aws_expiration");
+                }
+            }
 
-    fn try_from(file_path: PathFile) -> Result<Self, Self::Err> {
-        OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(file_path)?
-            .try_into()
+            match role_arn {
+                Some(role_arn) => ini.set(name, "oktaws_role_arn", &role_arn),
+                None => {
+                    ini.remove(name, "oktaws_role_arn");
+                }
+            }
+        }
+        ProfileCredentials::Iam {
+            access_key_id,
+            secret_access_key,
+        } => {
+            ini.set(name, "aws_access_key_id", &access_key_id);
+            ini.set(name, "aws_secret_access_key", &secret_access_key);
+        }
     }
+
+    Ok(())
 }
 
-impl TryFrom<File> for CredentialsStore {
-    type Err = Error;
+fn remove_credentials(ini: &mut Ini, name: &str) -> Result<(), Error> {
+    if let Some(ProfileCredentials::Iam { .. }) = profile_credentials(ini, name) {
+        bail!(
+            "Profile '{}' does not contain STS credentials. Ignoring",
+            name
+        );
+    }
 
-    fn try_from(mut file: File) -> Result<Self, Self::Err> {
-        let credentials = serde_ini::de::from_read(&file)?;
-        file.seek(SeekFrom::Start(0))?;
-        Ok(CredentialsStore { credentials, file })
+    for key in &[
+        "aws_access_key_id",
+        "aws_secret_access_key",
+        "aws_session_token",
+        "aws_expiration",
+        "oktaws_role_arn",
+    ] {
+        ini.remove(name, key);
+    }
+
+    if ini.entries(name).is_empty() {
+        ini.remove_section(name);
+    }
+
+    Ok(())
+}
+
+fn profile_credentials(ini: &Ini, name: &str) -> Option<ProfileCredentials> {
+    let get = |key: &str| ini.get(name, key).map(|value| value.to_owned());
+
+    match (
+        get("aws_access_key_id"),
+        get("aws_secret_access_key"),
+        get("aws_session_token"),
+    ) {
+        (Some(access_key_id), Some(secret_access_key), Some(session_token)) => {
+            Some(ProfileCredentials::Sts {
+                access_key_id,
+                secret_access_key,
+                session_token,
+                expiration: get("aws_expiration"),
+                role_arn: get("oktaws_role_arn"),
+            })
+        }
+        (Some(access_key_id), Some(secret_access_key), None) => Some(ProfileCredentials::Iam {
+            access_key_id,
+            secret_access_key,
+        }),
+        _ => None,
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
-#[serde(untagged)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ProfileCredentials {
     Sts {
-        #[serde(rename = "aws_access_key_id")]
         access_key_id: String,
-        #[serde(rename = "aws_secret_access_key")]
         secret_access_key: String,
-        #[serde(rename = "aws_session_token")]
         session_token: String,
+        // Not read by the AWS CLI, but lets us skip profiles which are still valid
+        expiration: Option<String>,
+        // Also ignored by the AWS CLI, and marks the profiles oktaws wrote
+        role_arn: Option<String>,
     },
     Iam {
-        #[serde(rename = "aws_access_key_id")]
         access_key_id: String,
-        #[serde(rename = "aws_secret_access_key")]
         secret_access_key: String,
     },
 }
@@ -130,22 +257,80 @@ impl From<Credentials> for ProfileCredentials {
             access_key_id: creds.access_key_id,
             secret_access_key: creds.secret_access_key,
             session_token: creds.session_token,
+            expiration: Some(creds.expiration),
+            role_arn: None,
+        }
+    }
+}
+
+impl From<RoleCredentials> for ProfileCredentials {
+    fn from(role_credentials: RoleCredentials) -> Self {
+        let creds = role_credentials.credentials;
+
+        ProfileCredentials::Sts {
+            access_key_id: creds.access_key_id,
+            secret_access_key: creds.secret_access_key,
+            session_token: creds.session_token,
+            expiration: Some(creds.expiration),
+            role_arn: Some(role_credentials.role_arn),
+        }
+    }
+}
+
+impl ProfileCredentials {
+    /// When STS credentials stop working, if it was recorded
+    pub fn expiration(&self) -> Option<DateTime<Utc>> {
+        match *self {
+            ProfileCredentials::Sts {
+                expiration: Some(ref expiration),
+                ..
+            } => parse_expiration(expiration),
+            _ => None,
+        }
+    }
+
+    /// The role oktaws got these credentials for, if it wrote them
+    pub fn role_arn(&self) -> Option<&str> {
+        match *self {
+            ProfileCredentials::Sts {
+                role_arn: Some(ref role_arn),
+                ..
+            } => Some(role_arn),
+            _ => None,
         }
     }
+
+    /// Whether these credentials will still work for at least `margin`
+    pub fn is_valid_for(&self, margin: Duration) -> bool {
+        is_valid_for(self.expiration(), margin)
+    }
+}
+
+/// Parses an STS expiration such as `2015-11-03T10:15:57Z`
+pub fn parse_expiration(expiration: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(expiration)
+        .ok()
+        .map(|e| e.with_timezone(&Utc))
+}
+
+/// Whether credentials expiring at `expiration` will still work for at least `margin`
+pub fn is_valid_for(expiration: Option<DateTime<Utc>>, margin: Duration) -> bool {
+    expiration
+        .map(|expiration| expiration > Utc::now() + margin)
+        .unwrap_or(false)
 }
 
 #[cfg(test)]
 mod tests {
-    extern crate tempfile;
-
-    use self::tempfile::Builder;
     use super::*;
-    use std::fs::File;
-    use std::io::{Read, Seek, SeekFrom, Write};
+    use chrono::TimeZone;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use tempfile::{Builder, NamedTempFile};
 
     #[test]
     fn parse_sts() {
-        let mut tmpfile: File = tempfile::tempfile().unwrap();
+        let mut tmpfile = NamedTempFile::new().unwrap();
         write!(
             tmpfile,
             "[example]
@@ -154,9 +339,8 @@ aws_secret_access_key=SECRET_ACCESS_KEY
 aws_session_token=SESSION_TOKEN"
         )
         .unwrap();
-        tmpfile.seek(SeekFrom::Start(0)).unwrap();
 
-        let credentials_store: CredentialsStore = tmpfile.try_into().unwrap();
+        let credentials_store: CredentialsStore = tmpfile.path().try_into().unwrap();
 
         let mut expected_credentials = BTreeMap::new();
         expected_credentials.insert(
@@ -165,15 +349,17 @@ aws_session_token=SESSION_TOKEN"
                 access_key_id: String::from("ACCESS_KEY"),
                 secret_access_key: String::from("SECRET_ACCESS_KEY"),
                 session_token: String::from("SESSION_TOKEN"),
+                expiration: None,
+                role_arn: None,
             },
         );
 
-        assert_eq!(credentials_store.credentials, expected_credentials);
+        assert_eq!(credentials_store.profiles(), expected_credentials);
     }
 
     #[test]
     fn double_entries() {
-        let mut tmpfile: File = tempfile::tempfile().unwrap();
+        let mut tmpfile = NamedTempFile::new().unwrap();
         write!(
             tmpfile,
             "
@@ -187,9 +373,8 @@ aws_secret_access_key=SECRET_ACCESS_KEY
 aws_session_token=SESSION_TOKEN"
         )
         .unwrap();
-        tmpfile.seek(SeekFrom::Start(0)).unwrap();
 
-        let credentials_store: CredentialsStore = tmpfile.try_into().unwrap();
+        let credentials_store: CredentialsStore = tmpfile.path().try_into().unwrap();
 
         let mut expected_credentials = BTreeMap::new();
         expected_credentials.insert(
@@ -198,10 +383,12 @@ aws_session_token=SESSION_TOKEN"
                 access_key_id: String::from("ACCESS_KEY"),
                 secret_access_key: String::from("SECRET_ACCESS_KEY"),
                 session_token: String::from("SESSION_TOKEN"),
+                expiration: None,
+                role_arn: None,
             },
         );
 
-        assert_eq!(credentials_store.credentials, expected_credentials);
+        assert_eq!(credentials_store.profiles(), expected_credentials);
     }
 
     #[test]
@@ -236,6 +423,8 @@ aws_session_token=SESSION_TOKEN"
                     access_key_id: String::from("ACCESS_KEY2"),
                     secret_access_key: String::from("SECRET_ACCESS_KEY2"),
                     session_token: String::from("SESSION_TOKEN2"),
+                    expiration: None,
+                    role_arn: None,
                 },
             )
             .unwrap();
@@ -250,13 +439,70 @@ aws_session_token=SESSION_TOKEN"
 
         assert_eq!(
             &buf,
-            "[example]\r\naws_access_key_id=ACCESS_KEY2\r\naws_secret_access_key=SECRET_ACCESS_KEY2\r\naws_session_token=SESSION_TOKEN2\r\n[existing]\r\naws_access_key_id=ACCESS_KEY\r\naws_secret_access_key=SECRET_ACCESS_KEY\r\n"
+            "
+[existing]
+aws_access_key_id=ACCESS_KEY
+aws_secret_access_key=SECRET_ACCESS_KEY
+[example]
+aws_access_key_id=ACCESS_KEY2
+aws_secret_access_key=SECRET_ACCESS_KEY2
+aws_session_token=SESSION_TOKEN2"
+        );
+    }
+
+    #[test]
+    fn remove_sts() {
+        let mut tmpfile = NamedTempFile::new().unwrap();
+        write!(
+            tmpfile,
+            "[existing]
+aws_access_key_id=ACCESS_KEY
+aws_secret_access_key=SECRET_ACCESS_KEY
+
+[example]
+aws_access_key_id=ACCESS_KEY
+aws_secret_access_key=SECRET_ACCESS_KEY
+aws_session_token=SESSION_TOKEN
+
+[regional]
+region=eu-west-1
+aws_access_key_id=ACCESS_KEY
+aws_secret_access_key=SECRET_ACCESS_KEY
+aws_session_token=SESSION_TOKEN
+"
+        )
+        .unwrap();
+
+        let mut credentials_store: CredentialsStore = tmpfile.path().try_into().unwrap();
+
+        assert!(credentials_store
+            .remove_profile(String::from("existing"))
+            .is_err());
+        credentials_store
+            .remove_profile(String::from("example"))
+            .unwrap();
+        credentials_store
+            .remove_profile(String::from("regional"))
+            .unwrap();
+        assert!(credentials_store.get_profile("example").is_none());
+
+        credentials_store.save().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(tmpfile.path()).unwrap(),
+            "[existing]
+aws_access_key_id=ACCESS_KEY
+aws_secret_access_key=SECRET_ACCESS_KEY
+
+[regional]
+region=eu-west-1
+"
         );
     }
 
     #[test]
     fn parse_iam() {
-        let mut tmpfile: File = tempfile::tempfile().unwrap();
+        let mut tmpfile = NamedTempFile::new().unwrap();
         write!(
             tmpfile,
             "[example]
@@ -264,9 +510,8 @@ aws_access_key_id=ACCESS_KEY
 aws_secret_access_key=SECRET_ACCESS_KEY"
         )
         .unwrap();
-        tmpfile.seek(SeekFrom::Start(0)).unwrap();
 
-        let credentials_store: CredentialsStore = tmpfile.try_into().unwrap();
+        let credentials_store: CredentialsStore = tmpfile.path().try_into().unwrap();
 
         let mut expected_credentials = BTreeMap::new();
         expected_credentials.insert(
@@ -277,6 +522,34 @@ aws_secret_access_key=SECRET_ACCESS_KEY"
             },
         );
 
-        assert_eq!(credentials_store.credentials, expected_credentials);
+        assert_eq!(credentials_store.profiles(), expected_credentials);
+    }
+
+    #[test]
+    fn parse_sts_expiration() {
+        let mut tmpfile = NamedTempFile::new().unwrap();
+        write!(
+            tmpfile,
+            "[example]
+aws_access_key_id=ACCESS_KEY
+aws_secret_access_key=SECRET_ACCESS_KEY
+aws_session_token=SESSION_TOKEN
+aws_expiration=2015-11-03T10:15:57Z
+oktaws_role_arn=arn:aws:iam::123456789012:role/role1"
+        )
+        .unwrap();
+
+        let credentials_store: CredentialsStore = tmpfile.path().try_into().unwrap();
+        let profile = credentials_store.get_profile("example").unwrap();
+
+        assert_eq!(
+            profile.expiration(),
+            Some(Utc.ymd(2015, 11, 3).and_hms(10, 15, 57))
+        );
+        assert!(!profile.is_valid_for(Duration::zero()));
+        assert_eq!(
+            profile.role_arn(),
+            Some("arn:aws:iam::123456789012:role/role1")
+        );
     }
 }
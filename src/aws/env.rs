@@ -0,0 +1,180 @@
+use failure::Error;
+use serde_json;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use aws::credential_process::ProcessCredentials;
+
+/// The environment variables the AWS CLI and SDKs read credentials from.
+///
+/// The profile should only be given if the AWS CLI knows it, as it fails on unknown profiles.
+pub fn variables(
+    credentials: &ProcessCredentials,
+    profile: Option<&str>,
+    region: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let mut variables = vec![
+        ("AWS_ACCESS_KEY_ID", credentials.access_key_id.clone()),
+        (
+            "AWS_SECRET_ACCESS_KEY",
+            credentials.secret_access_key.clone(),
+        ),
+        ("AWS_SESSION_TOKEN", credentials.session_token.clone()),
+        ("AWS_CREDENTIAL_EXPIRATION", credentials.expiration.clone()),
+    ];
+
+    if let Some(region) = region {
+        variables.push(("AWS_REGION", region.to_owned()));
+        variables.push(("AWS_DEFAULT_REGION", region.to_owned()));
+    }
+
+    if let Some(profile) = profile {
+        variables.push(("AWS_PROFILE", profile.to_owned()));
+    }
+
+    variables
+}
+
+/// How to print environment variables so that they can be loaded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+    Json,
+    Dotenv,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bash" | "sh" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "powershell" | "pwsh" => Ok(Shell::PowerShell),
+            "json" => Ok(Shell::Json),
+            "dotenv" => Ok(Shell::Dotenv),
+            _ => bail!(
+                "Unknown shell {}, expected one of bash, zsh, fish, powershell, json or dotenv",
+                s
+            ),
+        }
+    }
+}
+
+impl Shell {
+    /// Statements setting every variable, one per line
+    pub fn exports(self, variables: &[(&str, String)]) -> Result<String, Error> {
+        let export: fn(&str, &str) -> String = match self {
+            Shell::Bash | Shell::Zsh => {
+                |name, value| format!("export {}='{}'", name, value.replace('\'', r"'\''"))
+            }
+            Shell::Fish => |name, value| {
+                format!(
+                    "set -gx {} '{}';",
+                    name,
+                    value.replace('\\', r"\\").replace('\'', r"\'")
+                )
+            },
+            Shell::PowerShell => {
+                |name, value| format!("$Env:{} = '{}'", name, value.replace('\'', "''"))
+            }
+            Shell::Dotenv => |name, value| {
+                format!(
+                    "{}=\"{}\"",
+                    name,
+                    value.replace('\\', r"\\").replace('"', "\\\"")
+                )
+            },
+            // A single object rather than a statement per variable
+            Shell::Json => {
+                let object = variables
+                    .iter()
+                    .map(|&(name, ref value)| (name, value))
+                    .collect::<BTreeMap<_, _>>();
+
+                return Ok(serde_json::to_string_pretty(&object)?);
+            }
+        };
+
+        Ok(variables
+            .iter()
+            .map(|&(name, ref value)| export(name, value))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_variables() -> Vec<(&'static str, String)> {
+        vec![
+            ("AWS_ACCESS_KEY_ID", String::from("ACCESS_KEY")),
+            ("AWS_PROFILE", String::from("it's")),
+        ]
+    }
+
+    #[test]
+    fn posix_exports() {
+        assert_eq!(
+            Shell::Bash.exports(&example_variables()).unwrap(),
+            "export AWS_ACCESS_KEY_ID='ACCESS_KEY'\nexport AWS_PROFILE='it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn other_exports() {
+        assert_eq!(
+            Shell::Fish.exports(&example_variables()).unwrap(),
+            "set -gx AWS_ACCESS_KEY_ID 'ACCESS_KEY';\nset -gx AWS_PROFILE 'it\\'s';"
+        );
+        assert_eq!(
+            Shell::PowerShell.exports(&example_variables()).unwrap(),
+            "$Env:AWS_ACCESS_KEY_ID = 'ACCESS_KEY'\n$Env:AWS_PROFILE = 'it''s'"
+        );
+        assert_eq!(
+            Shell::Dotenv.exports(&example_variables()).unwrap(),
+            "AWS_ACCESS_KEY_ID=\"ACCESS_KEY\"\nAWS_PROFILE=\"it's\""
+        );
+        assert_eq!(
+            Shell::Json.exports(&example_variables()).unwrap(),
+            "{\n  \"AWS_ACCESS_KEY_ID\": \"ACCESS_KEY\",\n  \"AWS_PROFILE\": \"it's\"\n}"
+        );
+    }
+
+    #[test]
+    fn optional_variables() {
+        let credentials = ProcessCredentials {
+            version: 1,
+            access_key_id: String::from("ACCESS_KEY"),
+            secret_access_key: String::from("SECRET_ACCESS_KEY"),
+            session_token: String::from("SESSION_TOKEN"),
+            expiration: String::from("2015-11-03T10:15:57Z"),
+        };
+
+        let names = |variables: Vec<(&'static str, String)>| {
+            variables
+                .into_iter()
+                .map(|(name, _)| name)
+                .skip(4)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(names(variables(&credentials, None, None)).is_empty());
+        assert_eq!(
+            names(variables(&credentials, Some("example"), Some("eu-west-1"))),
+            vec!["AWS_REGION", "AWS_DEFAULT_REGION", "AWS_PROFILE"]
+        );
+    }
+
+    #[test]
+    fn parse_shell() {
+        assert_eq!("PowerShell".parse::<Shell>().unwrap(), Shell::PowerShell);
+        assert!("tcsh".parse::<Shell>().is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use failure::Error;
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind as IoErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+use aws::ini::Ini;
+
+// Like the limit of most operating systems
+const MAX_LINKS: usize = 40;
+
+/// Reads an AWS INI file, which is empty when it doesn't exist yet
+pub fn read(path: &Path) -> Result<Ini, Error> {
+    let mut contents = String::new();
+
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_string(&mut contents)?;
+        }
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(Ini::from(contents.as_str()))
+}
+
+/// Applies changes to the latest contents of an AWS INI file.
+///
+/// Other oktaws processes are kept out while the file is read, changed and written, and the
+/// file is replaced in one go, so that it is never seen half written.
+pub fn update<F>(path: &Path, change: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut Ini) -> Result<(), Error>,
+{
+    // Dotfile setups often link the file from elsewhere, so the link is kept and its target replaced
+    let path = resolve_links(path)?;
+    let dir = path
+        .parent()
+        .ok_or_else(|| format_err!("{} has no parent directory", path.display()))?;
+    fs::create_dir_all(dir)?;
+
+    let lock_path = lock_path(&path);
+    let lock = lock(&lock_path)?;
+
+    let mut ini = read(&path)?;
+    let result = change(&mut ini).and_then(|_| write(&path, dir, &ini));
+
+    remove_lock(&lock_path)?;
+    lock.unlock()?;
+
+    result
+}
+
+fn write(path: &Path, dir: &Path, ini: &Ini) -> Result<(), Error> {
+    // Temporary files are only readable by their owner, which is what new credentials need
+    let mut temp = NamedTempFile::new_in(dir)?;
+    temp.write_all(ini.to_string().as_bytes())?;
+    temp.as_file().sync_all()?;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(temp.path(), metadata.permissions())?;
+    }
+
+    temp.persist(path)?;
+
+    Ok(())
+}
+
+fn resolve_links(path: &Path) -> Result<PathBuf, Error> {
+    let mut path = path.to_path_buf();
+
+    for _ in 0..MAX_LINKS {
+        match fs::symlink_metadata(&path) {
+            Ok(ref metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(dir) => dir.join(target),
+                    None => target,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+
+    bail!("Too many levels of symbolic links in {}", path.display())
+}
+
+fn lock(lock_path: &Path) -> Result<File, Error> {
+    loop {
+        let lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(lock_path)?;
+        lock.lock_exclusive()?;
+
+        if is_same_file(&lock, lock_path) {
+            return Ok(lock);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+// The lock file is kept, so the file which was locked is always the one at the path
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, _path: &Path) -> bool {
+    true
+}
+
+// Removed while still locked, so that waiting processes notice and lock a new file instead
+#[cfg(unix)]
+fn remove_lock(lock_path: &Path) -> Result<(), Error> {
+    fs::remove_file(lock_path).map_err(|e| e.into())
+}
+
+// Windows only marks open files for deletion, and then fails to open them again until they are closed
+#[cfg(not(unix))]
+fn remove_lock(_lock_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut file_name = path
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    file_name.push(".lock");
+
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn update_new_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("aws").join("credentials");
+
+        update(&path, |ini| {
+            ini.set("example", "aws_access_key_id", "ACCESS_KEY");
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            read(&path).unwrap().get("example", "aws_access_key_id"),
+            Some("ACCESS_KEY")
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            assert!(!lock_path(&path).exists());
+
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn update_shorter() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("credentials");
+        fs::write(
+            &path,
+            "[example]\naws_session_token = A_VERY_LONG_SESSION_TOKEN\n",
+        )
+        .unwrap();
+
+        update(&path, |ini| {
+            ini.set("example", "aws_session_token", "SHORT");
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[example]\naws_session_token = SHORT\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn update_symlink() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("dotfiles").join("credentials");
+        let path = dir.path().join("credentials");
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        fs::write(&target, "[example]\naws_session_token = TOKEN\n").unwrap();
+        symlink("dotfiles/credentials", &path).unwrap();
+
+        update(&path, |ini| {
+            ini.set("example", "aws_session_token", "TOKEN2");
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(fs::symlink_metadata(&path)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs::read_to_string(&target).unwrap(),
+            "[example]\naws_session_token = TOKEN2\n"
+        );
+        assert!(!lock_path(&target).exists());
+    }
+}
@@ -0,0 +1,438 @@
+use std::fmt;
+
+/// An INI file, as used by the AWS CLI, which can be edited without losing
+/// comments, ordering, formatting or keys we don't know about.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ini {
+    lines: Vec<Line>,
+    crlf: bool,
+    trailing_newline: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Line {
+    Section {
+        name: String,
+        raw: String,
+    },
+    Entry {
+        key: String,
+        // Everything up to the value, so that editing keeps the key's formatting
+        prefix: String,
+        value: String,
+        suffix: String,
+    },
+    // Comments, blank lines and continuation lines
+    Other(String),
+}
+
+impl Line {
+    fn parse(raw: &str) -> Line {
+        let trimmed = raw.trim();
+
+        if trimmed.starts_with('[') {
+            if let Some(end) = trimmed.find(']') {
+                return Line::Section {
+                    name: trimmed[1..end].trim().to_owned(),
+                    raw: raw.to_owned(),
+                };
+            }
+        }
+
+        let is_comment = trimmed.starts_with('#') || trimmed.starts_with(';');
+        let is_continuation = raw.starts_with(|c: char| c.is_whitespace());
+
+        match raw.find('=') {
+            Some(eq) if !is_comment && !is_continuation => {
+                let rest = &raw[eq + 1..];
+                let value_start = eq + 1 + (rest.len() - rest.trim_start().len());
+                let value_end = raw.trim_end().len().max(value_start);
+
+                Line::Entry {
+                    key: raw[..eq].trim().to_owned(),
+                    prefix: raw[..value_start].to_owned(),
+                    value: raw[value_start..value_end].to_owned(),
+                    suffix: raw[value_end..].to_owned(),
+                }
+            }
+            _ => Line::Other(raw.to_owned()),
+        }
+    }
+
+    fn section_name(&self) -> Option<&str> {
+        match *self {
+            Line::Section { ref name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    fn is_blank(&self) -> bool {
+        match *self {
+            Line::Other(ref raw) => raw.trim().is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Whether this line is part of a key, either the key itself or the indented lines of its value
+    fn is_key(&self) -> bool {
+        match *self {
+            Line::Entry { .. } => true,
+            Line::Other(ref raw) => {
+                raw.starts_with(|c: char| c.is_whitespace()) && !self.is_blank()
+            }
+            Line::Section { .. } => false,
+        }
+    }
+
+    fn entry(&self) -> Option<(&str, &str)> {
+        match *self {
+            Line::Entry {
+                ref key, ref value, ..
+            } => Some((key, value)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Line::Section { ref raw, .. } | Line::Other(ref raw) => write!(f, "{}", raw),
+            Line::Entry {
+                ref prefix,
+                ref value,
+                ref suffix,
+                ..
+            } => write!(f, "{}{}{}", prefix, value, suffix),
+        }
+    }
+}
+
+impl Ini {
+    /// Names of the sections, in the order they appear
+    pub fn sections(&self) -> Vec<&str> {
+        let mut sections: Vec<&str> = Vec::new();
+
+        for name in self.lines.iter().filter_map(|l| l.section_name()) {
+            if !sections.contains(&name) {
+                sections.push(name);
+            }
+        }
+
+        sections
+    }
+
+    pub fn has_section(&self, section: &str) -> bool {
+        self.section_range(section).is_some()
+    }
+
+    /// The keys and values of a section, in the order they appear
+    pub fn entries(&self, section: &str) -> Vec<(&str, &str)> {
+        match self.section_range(section) {
+            Some((start, end)) => self.lines[start..end]
+                .iter()
+                .filter_map(|l| l.entry())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.entries(section)
+            .into_iter()
+            .find(|&(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Sets a value, keeping its place if the key already exists.
+    ///
+    /// New keys go after the last key of the section, and new sections at the end of the file.
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let (start, end) = match self.section_range(section) {
+            Some(range) => range,
+            None => {
+                if self
+                    .lines
+                    .last()
+                    .map_or(false, |l| !l.to_string().trim().is_empty())
+                {
+                    self.lines.push(Line::Other(String::new()));
+                }
+
+                self.lines.push(Line::Section {
+                    name: section.to_owned(),
+                    raw: format!("[{}]", section),
+                });
+
+                (self.lines.len(), self.lines.len())
+            }
+        };
+
+        for line in &mut self.lines[start..end] {
+            if let Line::Entry {
+                key: ref k,
+                value: ref mut v,
+                ..
+            } = *line
+            {
+                if k == key {
+                    *v = value.to_owned();
+                    return;
+                }
+            }
+        }
+
+        let position = self.lines[start..end]
+            .iter()
+            .rposition(|l| l.is_key())
+            .map(|i| start + i + 1)
+            .unwrap_or(start);
+        let separator = self.separator();
+
+        self.lines.insert(
+            position,
+            Line::Entry {
+                key: key.to_owned(),
+                prefix: format!("{}{}", key, separator),
+                value: value.to_owned(),
+                suffix: String::new(),
+            },
+        );
+    }
+
+    /// Removes a key, returning its value
+    pub fn remove(&mut self, section: &str, key: &str) -> Option<String> {
+        let (start, end) = self.section_range(section)?;
+        let index = self.lines[start..end]
+            .iter()
+            .position(|l| l.entry().map(|(k, _)| k == key).unwrap_or(false))?;
+
+        match self.lines.remove(start + index) {
+            Line::Entry { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Removes a section and its keys, returning whether it existed.
+    ///
+    /// Comments and blank lines after the last key are kept, as they usually introduce the next section.
+    pub fn remove_section(&mut self, section: &str) -> bool {
+        let (start, end) = match self.section_range(section) {
+            Some(range) => range,
+            None => return false,
+        };
+
+        let end = self.lines[start..end]
+            .iter()
+            .rposition(|l| l.is_key())
+            .map(|i| start + i + 1)
+            .unwrap_or(start);
+        let mut start = start - 1;
+
+        // Avoid leaving two blank lines where the section was
+        if start > 0
+            && self.lines[start - 1].is_blank()
+            && self.lines.get(end).map_or(true, |l| l.is_blank())
+        {
+            start -= 1;
+        }
+
+        self.lines.drain(start..end);
+
+        true
+    }
+
+    /// The lines of the first section with this name, excluding its header
+    fn section_range(&self, section: &str) -> Option<(usize, usize)> {
+        let header = self
+            .lines
+            .iter()
+            .position(|l| l.section_name() == Some(section))?;
+        let end = self.lines[header + 1..]
+            .iter()
+            .position(|l| l.section_name().is_some())
+            .map(|i| header + 1 + i)
+            .unwrap_or_else(|| self.lines.len());
+
+        Some((header + 1, end))
+    }
+
+    /// How keys are separated from values in this file, so that new keys look like the others
+    fn separator(&self) -> String {
+        self.lines
+            .iter()
+            .filter_map(|l| match *l {
+                Line::Entry {
+                    ref key,
+                    ref prefix,
+                    ..
+                } => Some(prefix[key.len()..].to_owned()),
+                _ => None,
+            })
+            .next()
+            .unwrap_or_else(|| String::from(" = "))
+    }
+}
+
+impl<'a> From<&'a str> for Ini {
+    fn from(s: &'a str) -> Self {
+        let content = if s.ends_with('\n') {
+            &s[..s.len() - 1]
+        } else {
+            s
+        };
+
+        let lines = if s.is_empty() {
+            Vec::new()
+        } else {
+            content
+                .split('\n')
+                .map(|l| Line::parse(l.trim_end_matches('\r')))
+                .collect()
+        };
+
+        Ini {
+            lines,
+            crlf: s.contains("\r\n"),
+            // New files end with a newline, like the ones the AWS CLI writes
+            trailing_newline: s.is_empty() || s.ends_with('\n'),
+        }
+    }
+}
+
+impl fmt::Display for Ini {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", newline)?;
+            }
+            write!(f, "{}", line)?;
+        }
+
+        if self.trailing_newline && !self.lines.is_empty() {
+            write!(f, "{}", newline)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREDENTIALS: &str = "# Managed by hand
+[default]
+aws_access_key_id = ACCESS_KEY
+aws_secret_access_key = SECRET_ACCESS_KEY
+region = eu-west-1
+
+; Managed by oktaws
+[example]
+aws_access_key_id = ACCESS_KEY
+aws_secret_access_key = SECRET_ACCESS_KEY
+aws_session_token = SESSION_TOKEN
+
+[other]
+s3 =
+  max_concurrent_requests = 10
+";
+
+    #[test]
+    fn round_trip() {
+        let ini = Ini::from(CREDENTIALS);
+
+        assert_eq!(ini.to_string(), CREDENTIALS);
+        assert_eq!(ini.sections(), vec!["default", "example", "other"]);
+        assert_eq!(ini.get("default", "region"), Some("eu-west-1"));
+        assert_eq!(ini.get("other", "s3"), Some(""));
+        assert_eq!(ini.get("other", "max_concurrent_requests"), None);
+    }
+
+    #[test]
+    fn edit_in_place() {
+        let mut ini = Ini::from(CREDENTIALS);
+
+        ini.set("example", "aws_session_token", "SESSION_TOKEN2");
+        ini.set("example", "aws_expiration", "2015-11-03T10:15:57Z");
+        assert_eq!(
+            ini.remove("default", "region"),
+            Some(String::from("eu-west-1"))
+        );
+
+        assert_eq!(
+            ini.to_string(),
+            "# Managed by hand
+[default]
+aws_access_key_id = ACCESS_KEY
+aws_secret_access_key = SECRET_ACCESS_KEY
+
+; Managed by oktaws
+[example]
+aws_access_key_id = ACCESS_KEY
+aws_secret_access_key = SECRET_ACCESS_KEY
+aws_session_token = SESSION_TOKEN2
+aws_expiration = 2015-11-03T10:15:57Z
+
+[other]
+s3 =
+  max_concurrent_requests = 10
+"
+        );
+    }
+
+    #[test]
+    fn remove_sections() {
+        let mut ini = Ini::from(CREDENTIALS);
+
+        assert!(ini.remove_section("default"));
+        assert!(ini.remove_section("other"));
+        assert!(!ini.remove_section("missing"));
+
+        assert_eq!(
+            ini.to_string(),
+            "# Managed by hand
+
+; Managed by oktaws
+[example]
+aws_access_key_id = ACCESS_KEY
+aws_secret_access_key = SECRET_ACCESS_KEY
+aws_session_token = SESSION_TOKEN
+"
+        );
+    }
+
+    #[test]
+    fn new_sections() {
+        let mut ini = Ini::from("[existing]\r\naws_access_key_id=ACCESS_KEY\r\n");
+        ini.set("example", "aws_access_key_id", "ACCESS_KEY2");
+
+        assert_eq!(
+            ini.to_string(),
+            "[existing]\r\naws_access_key_id=ACCESS_KEY\r\n\r\n[example]\r\naws_access_key_id=ACCESS_KEY2\r\n"
+        );
+
+        let mut ini = Ini::from("");
+        ini.set("example", "aws_access_key_id", "ACCESS_KEY");
+
+        assert_eq!(
+            ini.to_string(),
+            "[example]\naws_access_key_id = ACCESS_KEY\n"
+        );
+    }
+
+    #[test]
+    fn new_key_after_nested_value() {
+        let mut ini = Ini::from(
+            "[profile x]\nregion = eu-west-1\ns3 =\n  max_concurrent_requests = 10\n\n[other]\n",
+        );
+        ini.set("profile x", "output", "json");
+
+        assert_eq!(
+            ini.to_string(),
+            "[profile x]\nregion = eu-west-1\ns3 =\n  max_concurrent_requests = 10\noutput = json\n\n[other]\n"
+        );
+    }
+}
@@ -1,2 +1,8 @@
+pub mod config;
+pub mod credential_process;
 pub mod credentials;
+pub mod env;
+pub mod file;
+pub mod ini;
+pub mod partition;
 pub mod role;
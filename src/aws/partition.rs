@@ -0,0 +1,139 @@
+use failure::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A separate AWS cloud, with its own accounts, ARNs and endpoints
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Partition {
+    Aws,
+    AwsUsGov,
+    AwsCn,
+}
+
+impl FromStr for Partition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "aws" => Ok(Partition::Aws),
+            "aws-us-gov" => Ok(Partition::AwsUsGov),
+            "aws-cn" => Ok(Partition::AwsCn),
+            _ => bail!("Unknown AWS partition {}", s),
+        }
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Partition::Aws => "aws",
+            Partition::AwsUsGov => "aws-us-gov",
+            Partition::AwsCn => "aws-cn",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl Partition {
+    /// The partition an ARN such as `arn:aws-cn:iam::123456789012:role/role1` belongs to
+    pub fn from_arn(arn: &str) -> Result<Partition, Error> {
+        let splitted: Vec<&str> = arn.splitn(3, ':').collect();
+
+        match splitted.as_slice() {
+            ["arn", partition, _] => partition.parse(),
+            _ => bail!("Not an ARN: {}", arn),
+        }
+    }
+
+    /// Where SAML responses are posted to sign into the console
+    pub fn signin_url(self) -> &'static str {
+        match self {
+            Partition::Aws => "https://signin.aws.amazon.com/saml",
+            Partition::AwsUsGov => "https://signin.amazonaws-us-gov.com/saml",
+            Partition::AwsCn => "https://signin.amazonaws.cn/saml",
+        }
+    }
+
+    pub fn from_signin_url(url: &str) -> Option<Partition> {
+        [Partition::Aws, Partition::AwsUsGov, Partition::AwsCn]
+            .iter()
+            .find(|partition| partition.signin_url() == url)
+            .cloned()
+    }
+
+    /// The region to use STS in when none is configured
+    pub fn default_region(self) -> &'static str {
+        match self {
+            Partition::Aws => "us-east-1",
+            Partition::AwsUsGov => "us-gov-west-1",
+            Partition::AwsCn => "cn-north-1",
+        }
+    }
+
+    /// Whether a region such as `us-gov-west-1` is in this partition
+    pub fn has_region(self, region: &str) -> bool {
+        match self {
+            Partition::Aws => !region.starts_with("us-gov-") && !region.starts_with("cn-"),
+            Partition::AwsUsGov => region.starts_with("us-gov-"),
+            Partition::AwsCn => region.starts_with("cn-"),
+        }
+    }
+
+    /// The regional STS endpoint of a region in this partition
+    pub fn sts_endpoint(self, region: &str) -> String {
+        match self {
+            Partition::AwsCn => format!("https://sts.{}.amazonaws.com.cn", region),
+            _ => format!("https://sts.{}.amazonaws.com", region),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_arn() {
+        assert_eq!(
+            Partition::from_arn("arn:aws:iam::123456789012:role/role1").unwrap(),
+            Partition::Aws
+        );
+        assert_eq!(
+            Partition::from_arn("arn:aws-us-gov:iam::123456789012:role/role1").unwrap(),
+            Partition::AwsUsGov
+        );
+        assert_eq!(
+            Partition::from_arn("arn:aws-cn:iam::123456789012:role/role1").unwrap(),
+            Partition::AwsCn
+        );
+        assert!(Partition::from_arn("role/role1").is_err());
+    }
+
+    #[test]
+    fn endpoints() {
+        assert_eq!(
+            Partition::AwsCn.sts_endpoint("cn-northwest-1"),
+            "https://sts.cn-northwest-1.amazonaws.com.cn"
+        );
+        assert_eq!(
+            Partition::AwsUsGov.sts_endpoint(Partition::AwsUsGov.default_region()),
+            "https://sts.us-gov-west-1.amazonaws.com"
+        );
+        assert_eq!(
+            Partition::from_signin_url("https://signin.amazonaws.cn/saml"),
+            Some(Partition::AwsCn)
+        );
+    }
+
+    #[test]
+    fn regions() {
+        assert!(Partition::Aws.has_region("us-east-1"));
+        assert!(!Partition::Aws.has_region("us-gov-west-1"));
+        assert!(!Partition::Aws.has_region("cn-north-1"));
+        assert!(Partition::AwsUsGov.has_region("us-gov-east-1"));
+        assert!(!Partition::AwsUsGov.has_region("us-east-1"));
+        assert!(Partition::AwsCn.has_region("cn-northwest-1"));
+        assert!(!Partition::AwsCn.has_region("us-east-1"));
+    }
+}
@@ -1,12 +1,18 @@
-use failure::Error;
+use failure::{Error, ResultExt};
 use rusoto_core::request::HttpClient;
 use rusoto_core::Region;
 use rusoto_credential::StaticProvider;
-use rusoto_sts::{AssumeRoleWithSAMLRequest, AssumeRoleWithSAMLResponse, Sts, StsClient};
+use rusoto_sts::{
+    AssumeRoleRequest, AssumeRoleWithSAMLRequest, AssumeRoleWithSAMLResponse, Credentials, Sts,
+    StsClient,
+};
 
 use std::str;
 use std::str::FromStr;
 
+use aws::partition::Partition;
+use error::ErrorKind;
+
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub struct Role {
     pub provider_arn: String,
@@ -31,17 +37,54 @@ impl FromStr for Role {
 }
 
 impl Role {
+    /// The name of the role, without any path
     pub fn role_name(&self) -> Result<&str, Error> {
-        let splitted: Vec<&str> = self.role_arn.split('/').collect();
+        role_name(&self.role_arn)
+    }
 
-        match splitted.len() {
-            0 | 1 => bail!("Not enough elements in {}", self.role_arn),
-            2 => Ok(splitted[1]),
-            _ => bail!("Too many elements in {}", self.role_arn),
-        }
+    pub fn account_id(&self) -> Result<&str, Error> {
+        account_id(&self.role_arn)
+    }
+
+    pub fn partition(&self) -> Result<Partition, Error> {
+        Partition::from_arn(&self.role_arn)
+    }
+}
+
+/// The name of the role in a role ARN, without any path
+pub fn role_name(arn: &str) -> Result<&str, Error> {
+    let resource = arn_parts(arn)?[5];
+
+    match resource.splitn(2, '/').collect::<Vec<&str>>().as_slice() {
+        ["role", path] => path
+            .rsplit('/')
+            .next()
+            .ok_or_else(|| format_err!("No role name in {}", arn)),
+        _ => bail!("Not a role ARN: {}", arn),
+    }
+}
+
+pub fn account_id(arn: &str) -> Result<&str, Error> {
+    arn_parts(arn).map(|parts| parts[4])
+}
+
+// arn:partition:service:region:account-id:resource
+fn arn_parts(arn: &str) -> Result<Vec<&str>, Error> {
+    let parts: Vec<&str> = arn.splitn(6, ':').collect();
+
+    match parts.len() {
+        0..=5 => bail!("Not enough elements in {}", arn),
+        _ => Ok(parts),
     }
 }
 
+/// Credentials, and the role they were issued for
+#[derive(Clone, Debug)]
+pub struct RoleCredentials {
+    pub role_arn: String,
+    pub credentials: Credentials,
+}
+
 pub fn assume_role(
     Role {
         provider_arn,
@@ -49,6 +92,7 @@ pub fn assume_role(
     }: Role,
     saml_assertion: String,
     duration_seconds: Option<i64>,
+    region: Region,
 ) -> Result<AssumeRoleWithSAMLResponse, Error> {
     let req = AssumeRoleWithSAMLRequest {
         duration_seconds,
@@ -59,19 +103,80 @@ pub fn assume_role(
     };
 
     let provider = StaticProvider::new_minimal(String::from(""), String::from(""));
-    let client = StsClient::new_with(HttpClient::new()?, provider, Region::default());
+    let client = StsClient::new_with(HttpClient::new()?, provider, region.clone());
 
-    trace!("Assuming role: {:?}", &req);
+    trace!("Assuming role in {}: {:?}", region.name(), &req);
 
     client
         .assume_role_with_saml(req)
         .sync()
+        .context(ErrorKind::Sts)
+        .map_err(|e| e.into())
+}
+
+/// A role assumed with the credentials of the previous one, after the SAML role
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ChainedRole {
+    #[serde(rename = "role")]
+    pub role_arn: String,
+    pub external_id: Option<String>,
+    pub session_name: Option<String>,
+    #[serde(rename = "duration")]
+    pub duration_seconds: Option<i64>,
+}
+
+impl ChainedRole {
+    pub fn new(role_arn: String) -> ChainedRole {
+        ChainedRole {
+            role_arn,
+            external_id: None,
+            session_name: None,
+            duration_seconds: None,
+        }
+    }
+}
+
+pub fn assume_chained_role(
+    credentials: Credentials,
+    chained_role: &ChainedRole,
+    default_session_name: &str,
+    region: Region,
+) -> Result<Credentials, Error> {
+    let req = AssumeRoleRequest {
+        role_arn: chained_role.role_arn.clone(),
+        role_session_name: chained_role
+            .session_name
+            .clone()
+            .unwrap_or_else(|| default_session_name.to_owned()),
+        external_id: chained_role.external_id.clone(),
+        duration_seconds: chained_role.duration_seconds,
+        ..AssumeRoleRequest::default()
+    };
+
+    let provider = StaticProvider::new(
+        credentials.access_key_id,
+        credentials.secret_access_key,
+        Some(credentials.session_token),
+        None,
+    );
+    let client = StsClient::new_with(HttpClient::new()?, provider, region.clone());
+
+    trace!("Assuming chained role in {}: {:?}", region.name(), &req);
+
+    client
+        .assume_role(req)
+        .sync()
+        .context(ErrorKind::Sts)?
+        .credentials
+        .ok_or_else(|| format_err!("No credentials returned for {}", chained_role.role_arn))
+        .context(ErrorKind::Sts)
         .map_err(|e| e.into())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use toml;
 
     #[test]
     fn parse_attribute() {
@@ -85,4 +190,34 @@ mod tests {
 
         assert_eq!(attribute.parse::<Role>().unwrap(), expected_role);
     }
+
+    #[test]
+    fn parse_role_arn() {
+        let role: Role = "arn:aws-us-gov:iam::123456789012:saml-provider/okta-idp,arn:aws-us-gov:iam::123456789012:role/path/to/role1"
+            .parse()
+            .unwrap();
+
+        assert_eq!(role.role_name().unwrap(), "role1");
+        assert_eq!(role.account_id().unwrap(), "123456789012");
+        assert_eq!(role.partition().unwrap(), Partition::AwsUsGov);
+    }
+
+    #[test]
+    fn parse_chained_role() {
+        let chained_role: ChainedRole = toml::from_str(
+            "role = 'arn:aws:iam::111111111111:role/deploy'
+external_id = 'EXTERNAL_ID'
+duration = 900",
+        )
+        .unwrap();
+
+        assert_eq!(
+            chained_role,
+            ChainedRole {
+                external_id: Some(String::from("EXTERNAL_ID")),
+                duration_seconds: Some(900),
+                ..ChainedRole::new(String::from("arn:aws:iam::111111111111:role/deploy"))
+            }
+        );
+    }
 }
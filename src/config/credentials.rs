@@ -1,8 +1,11 @@
+use aws::credential_process::ProcessCredentials;
 use dialoguer::{Input, PasswordInput};
 use keyring::Keyring;
+use okta::sessions::CachedSession;
 use okta::Organization;
 #[cfg(windows)]
 use rpassword;
+use session::PasswordProvider;
 use username;
 
 use failure::Error;
@@ -16,6 +19,45 @@ pub fn get_username(org: &Organization) -> Result<String, Error> {
     input.interact().map_err(|e| e.into())
 }
 
+/// Reads Okta passwords from the system keyring, prompting for them when missing
+#[derive(Default)]
+pub struct KeyringPasswordProvider {
+    /// Always prompt, ignoring any password in the keyring
+    pub force_new: bool,
+}
+
+impl PasswordProvider for KeyringPasswordProvider {
+    fn get_password(&self, organization: &Organization, username: &str) -> Result<String, Error> {
+        get_password(organization, username, self.force_new)
+    }
+
+    fn save_password(
+        &self,
+        organization: &Organization,
+        username: &str,
+        password: &str,
+    ) -> Result<(), Error> {
+        save_credentials(organization, username, password)
+    }
+
+    fn get_session(&self, organization: &Organization, username: &str) -> Option<CachedSession> {
+        if self.force_new {
+            None
+        } else {
+            get_session(organization, username)
+        }
+    }
+
+    fn save_session(
+        &self,
+        organization: &Organization,
+        username: &str,
+        session: &CachedSession,
+    ) -> Result<(), Error> {
+        save_session(organization, username, session)
+    }
+}
+
 pub fn get_password(
     organization: &Organization,
     username: &str,
@@ -46,7 +88,7 @@ fn prompt_password(organization: &Organization, username: &str) -> Result<String
     url.set_username(username)
         .map_err(|_| format_err!("Cannot set username for URL"))?;
 
-    rpassword::prompt_password_stdout(&format!("Password for {}: ", url)).map_err(|e| e.into())
+    rpassword::prompt_password_stderr(&format!("Password for {}: ", url)).map_err(|e| e.into())
 }
 
 #[cfg(not(windows))]
@@ -75,3 +117,68 @@ pub fn save_credentials(
         .set_password(password)
         .map_err(|e| format_err!("{}", e))
 }
+
+pub fn get_session(organization: &Organization, username: &str) -> Option<CachedSession> {
+    match Keyring::new(&session_service(organization), username).get_password() {
+        Ok(session) => session
+            .parse::<CachedSession>()
+            .map_err(|e| debug!("Ignoring invalid cached Okta session ({})", e))
+            .ok(),
+        Err(e) => {
+            debug!("No cached Okta session because of {:?}", e);
+            None
+        }
+    }
+}
+
+pub fn save_session(
+    organization: &Organization,
+    username: &str,
+    session: &CachedSession,
+) -> Result<(), Error> {
+    debug!("Caching Okta session until {}", session.expires_at);
+
+    Keyring::new(&session_service(organization), username)
+        .set_password(&session.to_string())
+        .map_err(|e| format_err!("{}", e))
+}
+
+fn session_service(organization: &Organization) -> String {
+    format!("oktaws::okta::session::{}", organization.name)
+}
+
+/// Credentials handed out by `credential-process`, kept until they expire
+pub fn get_process_credentials(
+    organization: &Organization,
+    profile: &str,
+) -> Option<ProcessCredentials> {
+    match Keyring::new(&process_credentials_service(organization), profile).get_password() {
+        Ok(credentials) => credentials
+            .parse::<ProcessCredentials>()
+            .map_err(|e| debug!("Ignoring invalid cached AWS credentials ({})", e))
+            .ok(),
+        Err(e) => {
+            debug!("No cached AWS credentials because of {:?}", e);
+            None
+        }
+    }
+}
+
+pub fn save_process_credentials(
+    organization: &Organization,
+    profile: &str,
+    credentials: &ProcessCredentials,
+) -> Result<(), Error> {
+    debug!(
+        "Caching AWS credentials for {} until {}",
+        profile, credentials.expiration
+    );
+
+    Keyring::new(&process_credentials_service(organization), profile)
+        .set_password(&credentials.to_string())
+        .map_err(|e| format_err!("{}", e))
+}
+
+fn process_credentials_service(organization: &Organization) -> String {
+    format!("oktaws::aws::{}", organization.name)
+}
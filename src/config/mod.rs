@@ -3,7 +3,8 @@ pub mod organization;
 
 use config::organization::Organization;
 use dirs;
-use failure::Error;
+use error::ErrorKind;
+use failure::{Error, ResultExt};
 use std::env::var as env_var;
 use std::path::Path;
 use std::path::PathBuf;
@@ -12,18 +13,38 @@ use walkdir::WalkDir;
 
 pub struct Config {
     organizations: Vec<Organization>,
+    // Files in the oktaws home which are not valid organizations
+    unreadable: Vec<PathBuf>,
 }
 
 impl Config {
     pub fn new() -> Result<Config, Error> {
-        let oktaws_home = match env_var("OKTAWS_HOME") {
-            Ok(path) => PathBuf::from(path),
-            Err(_) => default_profile_location()?,
-        };
+        Ok(Config::from_dir(&oktaws_home()?))
+    }
 
-        Ok(Config {
-            organizations: organizations_from_dir(&oktaws_home).collect(),
-        })
+    fn from_dir(dir: &Path) -> Config {
+        let mut organizations = Vec::new();
+        let mut unreadable = Vec::new();
+
+        for (path, organization) in organizations_from_dir(dir) {
+            match organization {
+                Ok(organization) => organizations.push(organization),
+                Err(e) => {
+                    error!("{:?}", e);
+                    unreadable.push(path);
+                }
+            }
+        }
+
+        Config {
+            organizations,
+            unreadable,
+        }
+    }
+
+    /// Whether every organization file could be read, so that no profile is missing
+    pub fn fully_loaded(&self) -> bool {
+        self.unreadable.is_empty()
     }
 
     pub fn organizations(self) -> impl Iterator<Item = Organization> {
@@ -31,19 +52,25 @@ impl Config {
     }
 }
 
-fn organizations_from_dir(dir: &Path) -> impl Iterator<Item = Organization> {
+/// Where organization files are kept, `~/.oktaws` unless `OKTAWS_HOME` is set
+pub fn oktaws_home() -> Result<PathBuf, Error> {
+    match env_var("OKTAWS_HOME") {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Ok(default_profile_location().context(ErrorKind::Config)?),
+    }
+}
+
+fn organizations_from_dir(
+    dir: &Path,
+) -> impl Iterator<Item = (PathBuf, Result<Organization, Error>)> {
     WalkDir::new(dir)
         .follow_links(true)
         .into_iter()
         .filter_map(|r| r.ok())
         .filter(|e| e.file_type().is_file())
-        .map(|e| e.path().try_into())
-        .filter_map(|r| match r {
-            Ok(organization) => Some(organization),
-            Err(e) => {
-                error!("{:?}", e);
-                None
-            }
+        .map(|e| {
+            let organization = e.path().try_into();
+            (e.path().to_path_buf(), organization)
         })
 }
 
@@ -53,3 +80,35 @@ fn default_profile_location() -> Result<PathBuf, Error> {
         None => bail!("The environment variable HOME must be set."),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn unreadable_organizations() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("valid.toml"),
+            "username = 'user'\n\n[profiles]\nexample = 'Example App'\n",
+        )
+        .unwrap();
+
+        let config = Config::from_dir(dir.path());
+        assert!(config.fully_loaded());
+
+        fs::write(dir.path().join("broken.toml"), "[profiles\n").unwrap();
+
+        let config = Config::from_dir(dir.path());
+        assert!(!config.fully_loaded());
+        assert_eq!(
+            config
+                .organizations()
+                .map(|o| o.okta_organization.name)
+                .collect::<Vec<_>>(),
+            vec!["valid"]
+        );
+    }
+}
@@ -1,25 +1,50 @@
-use failure::Error;
-use std::fs::File;
+use failure::{Error, ResultExt};
+use std::fs::{self, File};
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use toml;
+use toml_edit::{self, Document, InlineTable, Item, Value};
 use try_from::TryFrom;
 
+use aws::config::ProfileConfig;
+use aws::role::ChainedRole;
 use config::credentials;
+use okta::factors::FactorSelector;
 use okta::Organization as OktaOrganization;
 
 #[derive(Clone, Debug)]
 pub struct Profile {
     pub name: String,
     pub application_name: String,
-    pub role: String,
+    /// Picked from the SAML roles when missing, if running interactively
+    pub role: Option<String>,
     pub duration_seconds: Option<i64>,
+    /// What to write to `~/.aws/config` for this profile, if anything
+    pub aws_config: Option<ProfileConfig>,
+    /// Overrides the regional STS endpoint, e.g. for VPC endpoints
+    pub sts_endpoint: Option<String>,
+    /// Roles to assume in turn after the SAML role, the last one giving the profile credentials
+    pub chain: Vec<ChainedRole>,
 }
 
 impl Profile {
+    pub fn new(name: String, application_name: String, role: Option<String>) -> Profile {
+        Profile {
+            name,
+            application_name,
+            role,
+            duration_seconds: None,
+            aws_config: None,
+            sts_endpoint: None,
+            chain: Vec::new(),
+        }
+    }
+
     fn from_entry(
         entry: (String, &toml::value::Value),
         default_role: Option<String>,
+        default_aws_config: Option<&ProfileConfig>,
+        default_sts_endpoint: Option<String>,
     ) -> Result<Profile, Error> {
         let application_name = if entry.1.is_table() {
             entry.1.get("application")
@@ -32,9 +57,9 @@ impl Profile {
         let role = if entry.1.is_table() {
             entry.1.get("role").and_then(|r| toml_to_string(r))
         } else {
-            default_role
+            None
         }
-        .ok_or_else(|| format_err!("No profile role or default role specified"))?;
+        .or(default_role);
 
         let duration_seconds = if entry.1.is_table() {
             entry.1.get("duration")
@@ -43,19 +68,70 @@ impl Profile {
         }
         .and_then(|d| d.as_integer());
 
+        let sts_endpoint = entry
+            .1
+            .get("sts_endpoint")
+            .and_then(|e| toml_to_string(e))
+            .or(default_sts_endpoint);
+
+        let chain = match entry.1.get("chain") {
+            Some(chain) => chain
+                .as_array()
+                .ok_or_else(|| format_err!("The chain of {} is not an array", entry.0))?
+                .iter()
+                .map(|role| match role.as_str() {
+                    Some(role_arn) => Ok(ChainedRole::new(role_arn.to_owned())),
+                    None => role
+                        .clone()
+                        .try_into::<ChainedRole>()
+                        .map_err(|e| format_err!("Invalid chained role for {} ({})", entry.0, e)),
+                })
+                .collect::<Result<Vec<ChainedRole>, Error>>()?,
+            None => Vec::new(),
+        };
+
+        let aws_config = match entry.1.get("aws_config") {
+            Some(aws_config) => Some(
+                aws_config
+                    .clone()
+                    .try_into::<ProfileConfig>()
+                    .map_err(|e| format_err!("Invalid aws_config for {} ({})", entry.0, e))?,
+            ),
+            None => None,
+        };
+
+        let aws_config = match (aws_config, default_aws_config) {
+            (Some(aws_config), Some(defaults)) => Some(aws_config.or(defaults)),
+            (aws_config, defaults) => aws_config.or_else(|| defaults.cloned()),
+        };
+
         Ok(Profile {
             name: entry.0,
             application_name,
             duration_seconds,
             role,
+            aws_config,
+            sts_endpoint,
+            chain,
         })
     }
+
+    /// The region set for this profile in the oktaws config
+    pub fn region(&self) -> Option<&str> {
+        self.aws_config
+            .as_ref()
+            .and_then(|aws_config| aws_config.region.as_ref())
+            .map(|region| region.as_str())
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Organization {
+    /// The TOML file the organization was read from
+    pub path: PathBuf,
     pub okta_organization: OktaOrganization,
     pub username: String,
+    pub factor: Option<FactorSelector>,
     pub profiles: Vec<Profile>,
 }
 
@@ -82,15 +158,43 @@ where
         let file_toml: toml::Value = toml::from_slice(&file_contents)?;
 
         let default_role: Option<String> = file_toml.get("role").and_then(|r| toml_to_string(r));
+        let default_sts_endpoint = file_toml
+            .get("sts_endpoint")
+            .and_then(|e| toml_to_string(e));
+
+        let default_aws_config =
+            match file_toml.get("aws_config") {
+                Some(aws_config) => Some(aws_config.clone().try_into::<ProfileConfig>().map_err(
+                    |e| format_err!("Invalid aws_config in {:?} ({})", path.as_ref(), e),
+                )?),
+                None => None,
+            };
 
         let profiles = file_toml
             .get("profiles")
             .and_then(|p| p.as_table())
             .ok_or_else(|| format_err!("No profiles table found"))?
             .iter()
-            .map(|(k, v)| Profile::from_entry((k.to_owned(), v), default_role.clone()))
+            .map(|(k, v)| {
+                Profile::from_entry(
+                    (k.to_owned(), v),
+                    default_role.clone(),
+                    default_aws_config.as_ref(),
+                    default_sts_endpoint.clone(),
+                )
+            })
             .collect::<Result<Vec<Profile>, Error>>()?;
 
+        let factor = match file_toml.get("factor") {
+            Some(factor) => Some(
+                factor
+                    .clone()
+                    .try_into::<FactorSelector>()
+                    .map_err(|e| format_err!("Invalid factor in {:?} ({})", path.as_ref(), e))?,
+            ),
+            None => None,
+        };
+
         let okta_organization = filename.parse()?;
 
         let username = match file_toml.get("username").and_then(|u| toml_to_string(u)) {
@@ -99,13 +203,323 @@ where
         };
 
         Ok(Organization {
+            path: path.as_ref().to_path_buf(),
             username,
+            factor,
             profiles,
             okta_organization,
         })
     }
 }
 
+impl Organization {
+    /// Sets the role of a profile in the organization's TOML file
+    pub fn save_role(&self, profile: &str, role: &str) -> Result<(), Error> {
+        info!(
+            "Saving role {} for profile {} in {:?}",
+            role, profile, self.path
+        );
+
+        self.update_toml(|document| {
+            let entry = document
+                .as_table_mut()
+                .get_mut("profiles")
+                .and_then(|p| p.as_table_mut())
+                .and_then(|p| p.get_mut(profile))
+                .ok_or_else(|| format_err!("No profile {} found", profile))?;
+
+            set_profile_key(entry, profile, "role", role)
+        })
+    }
+
+    /// Adds profiles to the organization's TOML file, creating it if needed.
+    ///
+    /// Profiles which are already in the file get the new application and role,
+    /// and keep their other settings.
+    pub fn save_profiles(&self, profiles: &[Profile]) -> Result<(), Error> {
+        info!("Saving {} profiles in {:?}", profiles.len(), self.path);
+
+        let username = self.username.clone();
+        self.update_toml(|document| {
+            let table = document.as_table_mut();
+
+            if !table.contains_key("username") {
+                table["username"] = toml_edit::value(username);
+            }
+
+            let profiles_table = table
+                .entry("profiles")
+                .or_insert(toml_edit::table())
+                .as_table_mut()
+                .ok_or_else(|| format_err!("Invalid profiles table"))?;
+
+            for profile in profiles {
+                if profiles_table.contains_key(&profile.name) {
+                    let entry = &mut profiles_table[&profile.name];
+                    set_profile_key(
+                        entry,
+                        &profile.name,
+                        "application",
+                        &profile.application_name,
+                    )?;
+                    if let Some(ref role) = profile.role {
+                        set_profile_key(entry, &profile.name, "role", role)?;
+                    }
+                    continue;
+                }
+
+                let mut entry = toml_edit::value(profile.application_name.as_str());
+                if let Some(ref role) = profile.role {
+                    set_profile_key(&mut entry, &profile.name, "role", role)?;
+                }
+
+                profiles_table[&profile.name] = entry;
+            }
+
+            Ok(())
+        })
+    }
+
+    // Edited in place, so that comments and formatting are kept
+    fn update_toml<F>(&self, change: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Document) -> Result<(), Error>,
+    {
+        let mut document = if self.path.exists() {
+            fs::read_to_string(&self.path)?.parse::<Document>()?
+        } else {
+            Document::new()
+        };
+
+        change(&mut document).with_context(|_| format!("Could not update {:?}", self.path))?;
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, document.to_string())?;
+
+        Ok(())
+    }
+}
+
+/// Sets a key of a profile, turning `name = 'application'` into an inline table if needed
+fn set_profile_key(entry: &mut Item, profile: &str, key: &str, value: &str) -> Result<(), Error> {
+    if let Some(current) = entry.as_value().filter(|v| v.is_str()).cloned() {
+        let replacement = if key == "application" {
+            Value::from(value)
+        } else {
+            let mut table = InlineTable::default();
+            table.get_or_insert("application", current.as_str().unwrap_or_default());
+            table.fmt();
+            Value::from(table)
+        };
+
+        let decor = current.decor();
+        *entry = Item::Value(toml_edit::decorated(
+            replacement,
+            decor.prefix(),
+            decor.suffix(),
+        ));
+
+        if key == "application" {
+            return Ok(());
+        }
+    }
+
+    if let Some(table) = entry.as_table_mut() {
+        table[key] = toml_edit::value(value);
+        return Ok(());
+    }
+
+    let table = entry
+        .as_inline_table_mut()
+        .ok_or_else(|| format_err!("Invalid profile {}", profile))?;
+    *table.get_or_insert(key, value) = Value::from(value);
+    table.fmt();
+
+    Ok(())
+}
+
 fn toml_to_string(value: &toml::value::Value) -> Option<String> {
     value.as_str().map(|r| r.to_owned())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use try_from::TryInto;
+
+    #[test]
+    fn parse_sts_endpoints() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("example.toml");
+        fs::write(
+            &path,
+            "username = 'user'
+sts_endpoint = 'https://sts.eu-west-1.amazonaws.com'
+
+[profiles]
+default = 'App'
+vpc = { application = 'App', sts_endpoint = 'https://vpce-1234.sts.us-east-1.vpce.amazonaws.com' }
+",
+        )
+        .unwrap();
+
+        let organization: Organization = path.as_path().try_into().unwrap();
+        let endpoint = |name: &str| {
+            organization
+                .profiles
+                .iter()
+                .find(|p| p.name == name)
+                .and_then(|p| p.sts_endpoint.clone())
+        };
+
+        assert_eq!(
+            endpoint("default"),
+            Some(String::from("https://sts.eu-west-1.amazonaws.com"))
+        );
+        assert_eq!(
+            endpoint("vpc"),
+            Some(String::from(
+                "https://vpce-1234.sts.us-east-1.vpce.amazonaws.com"
+            ))
+        );
+    }
+
+    #[test]
+    fn save_profiles() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("example.toml");
+        fs::write(
+            &path,
+            "username = 'user'
+role = 'default_role'
+
+[profiles]
+existing = 'Existing App'
+",
+        )
+        .unwrap();
+
+        let organization: Organization = path.as_path().try_into().unwrap();
+        organization
+            .save_profiles(&[Profile::new(
+                String::from("new"),
+                String::from("New App"),
+                Some(String::from("new_role")),
+            )])
+            .unwrap();
+        organization.save_role("existing", "other_role").unwrap();
+
+        let organization: Organization = path.as_path().try_into().unwrap();
+        let mut profiles = organization
+            .profiles
+            .iter()
+            .map(|p| (p.name.as_str(), p.application_name.as_str(), p.role.clone()))
+            .collect::<Vec<_>>();
+        profiles.sort();
+
+        assert_eq!(organization.username, "user");
+        assert_eq!(
+            profiles,
+            vec![
+                ("existing", "Existing App", Some(String::from("other_role"))),
+                ("new", "New App", Some(String::from("new_role"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn save_profiles_keeps_existing_settings() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("example.toml");
+        fs::write(
+            &path,
+            "username = 'user'
+
+[profiles]
+existing = { application = 'Old App', duration = 3600, sts_endpoint = 'https://sts.example.com' }
+plain = 'Plain App'
+",
+        )
+        .unwrap();
+
+        let organization: Organization = path.as_path().try_into().unwrap();
+        organization
+            .save_profiles(&[
+                Profile::new(
+                    String::from("existing"),
+                    String::from("New App"),
+                    Some(String::from("new_role")),
+                ),
+                Profile::new(String::from("plain"), String::from("Other App"), None),
+            ])
+            .unwrap();
+
+        let organization: Organization = path.as_path().try_into().unwrap();
+        let existing = organization
+            .profiles
+            .iter()
+            .find(|p| p.name == "existing")
+            .unwrap();
+        assert_eq!(existing.application_name, "New App");
+        assert_eq!(existing.role, Some(String::from("new_role")));
+        assert_eq!(existing.duration_seconds, Some(3600));
+        assert_eq!(
+            existing.sts_endpoint,
+            Some(String::from("https://sts.example.com"))
+        );
+
+        let plain = organization
+            .profiles
+            .iter()
+            .find(|p| p.name == "plain")
+            .unwrap();
+        assert_eq!(plain.application_name, "Other App");
+    }
+
+    #[test]
+    fn save_role_keeps_comments() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("example.toml");
+        fs::write(
+            &path,
+            "# Okta login
+username = 'user'
+
+[profiles]
+# Production account
+production = { application = 'Production App', duration = 3600 }
+staging = 'Staging App' # shared
+",
+        )
+        .unwrap();
+
+        let organization: Organization = path.as_path().try_into().unwrap();
+        organization.save_role("production", "admin").unwrap();
+        organization.save_role("staging", "dev").unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("# Okta login"));
+        assert!(contents.contains("# Production account"));
+        assert!(contents.contains("# shared"));
+
+        let organization: Organization = path.as_path().try_into().unwrap();
+        let production = organization
+            .profiles
+            .iter()
+            .find(|p| p.name == "production")
+            .unwrap();
+        assert_eq!(production.role, Some(String::from("admin")));
+        assert_eq!(production.duration_seconds, Some(3600));
+
+        let staging = organization
+            .profiles
+            .iter()
+            .find(|p| p.name == "staging")
+            .unwrap();
+        assert_eq!(staging.application_name, "Staging App");
+        assert_eq!(staging.role, Some(String::from("dev")));
+    }
+}
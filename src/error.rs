@@ -0,0 +1,112 @@
+use failure::{Context, Error};
+use reqwest;
+
+use okta::auth::LoginError;
+use okta::ExtractSamlResponseError;
+
+/// What went wrong, attached to errors with `ResultExt::context`.
+///
+/// Each kind is reported with its own process exit code, so that scripts wrapping oktaws
+/// can tell a wrong password apart from a missing role or an unreachable network.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Fail)]
+pub enum ErrorKind {
+    #[fail(display = "Invalid configuration")]
+    Config,
+    #[fail(display = "Okta authentication failed")]
+    OktaAuth,
+    #[fail(display = "MFA verification failed")]
+    Mfa,
+    #[fail(display = "Okta application not found")]
+    ApplicationNotFound,
+    #[fail(display = "AWS role not found")]
+    RoleNotFound,
+    #[fail(display = "Invalid SAML response")]
+    Saml,
+    #[fail(display = "AWS STS request failed")]
+    Sts,
+    #[fail(display = "Could not update the AWS credentials file")]
+    CredentialsFile,
+    #[fail(display = "Network error")]
+    Network,
+    #[fail(display = "Could not update the AWS config file")]
+    ConfigFile,
+    #[fail(display = "Unexpected error")]
+    Other,
+}
+
+impl ErrorKind {
+    /// Finds the most specific kind in the error chain
+    pub fn of(error: &Error) -> ErrorKind {
+        error
+            .iter_chain()
+            .filter_map(|cause| {
+                if let Some(context) = cause.downcast_ref::<Context<ErrorKind>>() {
+                    return Some(*context.get_context());
+                }
+
+                if let Some(login_error) = cause.downcast_ref::<LoginError>() {
+                    return Some(match *login_error {
+                        LoginError::MfaEnroll(_) | LoginError::MfaChallenge => ErrorKind::Mfa,
+                        _ => ErrorKind::OktaAuth,
+                    });
+                }
+
+                if cause.downcast_ref::<ExtractSamlResponseError>().is_some() {
+                    return Some(ErrorKind::Saml);
+                }
+
+                // Requests that got a response are classified by whoever made them
+                match cause.downcast_ref::<reqwest::Error>() {
+                    Some(e) if e.status().is_none() && !e.is_serialization() => {
+                        Some(ErrorKind::Network)
+                    }
+                    _ => None,
+                }
+            })
+            .last()
+            .unwrap_or(ErrorKind::Other)
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Config => 2,
+            ErrorKind::OktaAuth => 3,
+            ErrorKind::Mfa => 4,
+            ErrorKind::ApplicationNotFound => 5,
+            ErrorKind::RoleNotFound => 6,
+            ErrorKind::Saml => 7,
+            ErrorKind::Sts => 8,
+            ErrorKind::CredentialsFile => 9,
+            ErrorKind::Network => 10,
+            ErrorKind::ConfigFile => 11,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::ResultExt;
+
+    #[test]
+    fn innermost_kind() {
+        let error: Error = Err::<(), Error>(format_err!("Unknown role"))
+            .context(ErrorKind::RoleNotFound)
+            .context(ErrorKind::Config)
+            .unwrap_err()
+            .into();
+
+        assert_eq!(ErrorKind::of(&error), ErrorKind::RoleNotFound);
+        assert_eq!(ErrorKind::of(&error).exit_code(), 6);
+    }
+
+    #[test]
+    fn untagged_kind() {
+        assert_eq!(ErrorKind::of(&format_err!("Oops")), ErrorKind::Other);
+        assert_eq!(
+            ErrorKind::of(&ExtractSamlResponseError::NotFound.into()),
+            ErrorKind::Saml
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! Generates temporary AWS credentials with Okta.
+//!
+//! The whole Okta login → SAML assertion → STS pipeline is available through [`Builder`]:
+//!
+//! ```no_run
+//! # extern crate oktaws;
+//! # fn main() -> Result<(), oktaws::Error> {
+//! use oktaws::config::credentials::KeyringPasswordProvider;
+//! use oktaws::config::Config;
+//! use oktaws::Builder;
+//!
+//! for organization in Config::new()?.organizations() {
+//!     let session = Builder::new(organization.clone())
+//!         .password_provider(KeyringPasswordProvider::default())
+//!         .login()?;
+//!
+//!     for profile in &organization.profiles {
+//!         let credentials = session.credentials(profile)?;
+//!         println!("{}: {}", profile.name, credentials.access_key_id);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+extern crate base64;
+extern crate chrono;
+extern crate dialoguer;
+#[macro_use]
+extern crate failure;
+extern crate keyring;
+extern crate kuchiki;
+#[macro_use]
+extern crate log;
+extern crate regex;
+extern crate reqwest;
+#[cfg(windows)]
+extern crate rpassword;
+extern crate rusoto_core;
+extern crate rusoto_credential;
+extern crate rusoto_sts;
+#[macro_use]
+extern crate serde_derive;
+extern crate dirs;
+extern crate fs2;
+extern crate itertools;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_str;
+extern crate sxd_document;
+extern crate sxd_xpath;
+extern crate tempfile;
+extern crate toml;
+extern crate toml_edit;
+extern crate try_from;
+extern crate username;
+extern crate walkdir;
+
+pub mod aws;
+pub mod config;
+pub mod error;
+pub mod okta;
+pub mod saml;
+pub mod session;
+
+pub use error::ErrorKind;
+pub use failure::Error;
+pub use rusoto_sts::Credentials;
+pub use session::{AwsApplication, Builder, PasswordProvider, Session};
@@ -1,62 +1,47 @@
-extern crate base64;
+extern crate atty;
+extern crate chrono;
 extern crate dialoguer;
 #[macro_use]
 extern crate failure;
-extern crate keyring;
-extern crate kuchiki;
+extern crate glob;
 #[macro_use]
 extern crate log;
-extern crate path_abs;
+extern crate oktaws;
 extern crate pretty_env_logger;
-extern crate regex;
-extern crate reqwest;
-#[cfg(windows)]
-extern crate rpassword;
-extern crate rusoto_core;
-extern crate rusoto_credential;
-extern crate rusoto_sts;
-#[macro_use]
-extern crate serde_derive;
-extern crate dirs;
-extern crate glob;
 extern crate rayon;
-extern crate serde;
-extern crate serde_ini;
-extern crate serde_str;
+#[macro_use]
+extern crate serde_json;
 extern crate structopt;
 #[allow(unused_imports)]
 #[macro_use]
 extern crate structopt_derive;
-extern crate itertools;
-extern crate sxd_document;
-extern crate sxd_xpath;
-extern crate toml;
+#[cfg(test)]
+extern crate tempfile;
+#[cfg(test)]
 extern crate try_from;
-extern crate username;
-extern crate walkdir;
-
-mod aws;
-mod config;
-mod okta;
-mod saml;
-
-use aws::credentials::CredentialsStore;
-use aws::role::Role;
-use config::credentials;
-use config::organization::Organization;
-use config::organization::Profile;
-use config::Config;
-use failure::Error;
+
+use atty::Stream;
+use chrono::{DateTime, Utc};
+use dialoguer::Input;
+use failure::{Error, ResultExt};
 use glob::Pattern;
-use okta::auth::LoginRequest;
-use okta::client::Client as OktaClient;
+use oktaws::aws::config::ConfigStore;
+use oktaws::aws::credential_process::ProcessCredentials;
+use oktaws::aws::credentials::{CredentialsStore, ProfileCredentials};
+use oktaws::aws::env::{self as aws_env, Shell};
+use oktaws::aws::role::{self, Role, RoleCredentials};
+use oktaws::config::credentials::{self, KeyringPasswordProvider};
+use oktaws::config::organization::{Organization, Profile};
+use oktaws::config::{oktaws_home, Config};
+use oktaws::okta::Organization as OktaOrganization;
+use oktaws::{AwsApplication, Builder, ErrorKind, Session};
 use rayon::iter::IntoParallelRefIterator;
 use rayon::iter::ParallelIterator;
-use rusoto_sts::Credentials;
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::env;
+use std::process;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Clone, StructOpt, Debug)]
@@ -89,11 +74,101 @@ pub struct Opt {
     /// Run in an asynchronous manner (parallel)
     #[structopt(short = "a", long = "async")]
     pub asynchronous: bool,
+
+    /// Seconds to wait for an MFA push notification to be approved
+    #[structopt(long = "mfa-timeout", default_value = "60")]
+    pub mfa_timeout: u64,
+
+    /// Refresh credentials expiring within this many seconds, skipping the others
+    #[structopt(long = "refresh-margin", default_value = "600")]
+    pub refresh_margin: i64,
+
+    /// Also removes the profiles oktaws wrote which have expired or are no longer configured
+    #[structopt(long = "clean")]
+    pub clean: bool,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, StructOpt, Debug)]
+pub enum Command {
+    /// Prints credentials in the format expected by the AWS `credential_process` setting
+    #[structopt(name = "credential-process")]
+    CredentialProcess {
+        /// Profile to print credentials for
+        profile: String,
+    },
+
+    /// Runs a command with credentials in its environment, e.g. `oktaws exec prod -- aws s3 ls`
+    #[structopt(name = "exec")]
+    Exec {
+        /// Profile to run the command with
+        profile: String,
+
+        /// Command to run, and its arguments
+        #[structopt(raw(required = "true"))]
+        command: Vec<String>,
+    },
+
+    /// Prints credentials as environment variables, e.g. `eval $(oktaws env prod)`
+    #[structopt(name = "env")]
+    Env {
+        /// Profile to print credentials for
+        profile: String,
+
+        /// Format of the output: bash, zsh, fish, powershell, json or dotenv
+        #[structopt(long = "shell", default_value = "bash", parse(try_from_str))]
+        shell: Shell,
+    },
+
+    /// Creates or extends an organization file with the AWS roles available in Okta
+    #[structopt(name = "init")]
+    Init {
+        /// Okta organization, as in <organization>.okta.com
+        organization: String,
+    },
+
+    /// Lists organizations and their profiles
+    #[structopt(name = "list")]
+    List {
+        /// Also lists the AWS applications and roles available in Okta, which requires logging in
+        #[structopt(long = "remote")]
+        remote: bool,
+    },
+
+    /// Shows the credentials oktaws wrote to the AWS credentials file, and when they expire
+    #[structopt(name = "status")]
+    Status {
+        /// Prints a JSON array instead, e.g. for shell prompts
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Removes the profiles oktaws wrote which have expired or are no longer configured
+    #[structopt(name = "clean")]
+    Clean {
+        /// Only prints the profiles which would be removed
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
 }
 
-fn main() -> Result<(), Error> {
-    let opt = Opt::from_args();
+fn main() {
+    if let Err(e) = run(Opt::from_args()) {
+        let mut causes = e.iter_chain();
+        if let Some(error) = causes.next() {
+            eprintln!("Error: {}", error);
+        }
+        for cause in causes {
+            eprintln!("Caused by: {}", cause);
+        }
+
+        process::exit(ErrorKind::of(&e).exit_code());
+    }
+}
 
+fn run(opt: Opt) -> Result<(), Error> {
     let log_level = match opt.verbosity {
         0 => "info",
         1 => "debug",
@@ -103,17 +178,48 @@ fn main() -> Result<(), Error> {
 
     pretty_env_logger::init();
 
+    match opt.command {
+        Some(Command::CredentialProcess { ref profile }) => credential_process(&opt, profile),
+        Some(Command::Exec {
+            ref profile,
+            ref command,
+        }) => exec(&opt, profile, command),
+        Some(Command::Env { ref profile, shell }) => print_env(&opt, profile, shell),
+        Some(Command::Init { ref organization }) => init(&opt, organization),
+        Some(Command::List { remote }) => list(&opt, remote),
+        Some(Command::Status { json }) => status(&opt, json),
+        Some(Command::Clean { dry_run }) => clean(&opt, dry_run),
+        None => refresh(&opt),
+    }
+}
+
+/// Updates the AWS credentials file with every matching profile
+fn refresh(opt: &Opt) -> Result<(), Error> {
     let config = Config::new()?;
+    let remove_unconfigured = opt.clean && unconfigured_removable(opt, &config);
+    let organizations = config.organizations().collect::<Vec<Organization>>();
+    let configured_profiles = configured_profiles(
+        organizations
+            .iter()
+            .filter(|o| opt.organizations.matches(&o.okta_organization.name))
+            .cloned(),
+    );
 
     let credentials_store = Arc::new(Mutex::new(CredentialsStore::new()?));
+    let mut config_store = ConfigStore::new()?;
+    let refresh_margin = chrono::Duration::seconds(opt.refresh_margin);
 
-    let mut organizations = config
-        .organizations()
+    let mut organizations = organizations
+        .into_iter()
         .filter(|o| opt.organizations.matches(&o.okta_organization.name))
         .peekable();
 
     if organizations.peek().is_none() {
-        bail!("No organizations found called {}", opt.organizations);
+        return Err(
+            format_err!("No organizations found called {}", opt.organizations)
+                .context(ErrorKind::Config)
+                .into(),
+        );
     }
 
     for organization in organizations {
@@ -122,19 +228,6 @@ fn main() -> Result<(), Error> {
             organization.okta_organization.name
         );
 
-        let mut okta_client = OktaClient::new(organization.okta_organization.clone());
-        let username = organization.username.to_owned();
-        let password =
-            credentials::get_password(&organization.okta_organization, &username, opt.force_new)?;
-
-        let session_token = okta_client.get_session_token(&LoginRequest::from_credentials(
-            username.clone(),
-            password.clone(),
-        ))?;
-
-        let session_id = okta_client.new_session(session_token, &HashSet::new())?.id;
-        okta_client.set_session_id(session_id.clone());
-
         let profiles = organization
             .profiles
             .clone()
@@ -150,10 +243,43 @@ fn main() -> Result<(), Error> {
             continue;
         }
 
-        let credentials_folder = |mut acc: HashMap<String, Credentials>,
+        for profile in &profiles {
+            if let Some(ref aws_config) = profile.aws_config {
+                config_store.set_profile(profile.name.clone(), aws_config.clone())?;
+            }
+        }
+
+        let profiles = profiles
+            .into_iter()
+            .filter(|profile| {
+                let still_valid = !opt.force_new
+                    && credentials_store
+                        .lock()
+                        .unwrap()
+                        .get_profile(&profile.name)
+                        .map(|credentials| credentials.is_valid_for(refresh_margin))
+                        .unwrap_or(false);
+
+                if still_valid {
+                    info!("Credentials for {} are still valid, skipping", profile.name);
+                }
+
+                !still_valid
+            })
+            .collect::<Vec<Profile>>();
+
+        if profiles.is_empty() {
+            continue;
+        }
+
+        let session = builder(opt, organization.clone())
+            .aws_config(config_store.clone())
+            .login()?;
+
+        let credentials_folder = |mut acc: HashMap<String, RoleCredentials>,
                                   profile: &Profile|
-         -> Result<HashMap<String, Credentials>, Error> {
-            let credentials = fetch_credentials(&okta_client, &organization, &profile)?;
+         -> Result<HashMap<String, RoleCredentials>, Error> {
+            let credentials = session.role_credentials(&profile)?;
             acc.insert(profile.name.clone(), credentials);
 
             Ok(acc)
@@ -183,10 +309,27 @@ fn main() -> Result<(), Error> {
                 .unwrap()
                 .set_profile(name.clone(), creds)?;
         }
+    }
 
-        credentials::save_credentials(&organization.okta_organization, &username, &password)?;
+    if opt.clean {
+        let mut credentials_store = credentials_store.lock().unwrap();
+
+        for (name, reason) in stale_profiles(
+            opt,
+            &credentials_store,
+            &configured_profiles,
+            remove_unconfigured,
+        ) {
+            info!(
+                "Removing {} from the AWS credentials file ({})",
+                name, reason
+            );
+            credentials_store.remove_profile(name)?;
+        }
     }
 
+    config_store.save()?;
+
     Arc::try_unwrap(credentials_store)
         .map_err(|_| format_err!("Failed to un-reference count the credentials store"))?
         .into_inner()
@@ -194,71 +337,659 @@ fn main() -> Result<(), Error> {
         .save()
 }
 
-fn fetch_credentials(
-    client: &OktaClient,
-    organization: &Organization,
+/// Prints the credentials of a single profile as JSON, for the AWS `credential_process` setting.
+///
+/// Only the JSON document goes to stdout, since that is what the AWS CLI and SDKs read.
+fn credential_process(opt: &Opt, profile_name: &str) -> Result<(), Error> {
+    let (_, credentials) = profile_credentials(opt, profile_name, ConfigStore::new()?)?;
+
+    println!("{}", credentials);
+
+    Ok(())
+}
+
+/// Prints the credentials of a single profile as statements setting environment variables
+fn print_env(opt: &Opt, profile_name: &str, shell: Shell) -> Result<(), Error> {
+    let aws_config = ConfigStore::new()?;
+    let (profile, credentials) = profile_credentials(opt, profile_name, aws_config.clone())?;
+
+    println!(
+        "{}",
+        shell.exports(&env_variables(&profile, &credentials, &aws_config)?)?
+    );
+
+    Ok(())
+}
+
+/// Runs a command with the credentials of a single profile in its environment
+fn exec(opt: &Opt, profile_name: &str, command: &[String]) -> Result<(), Error> {
+    let aws_config = ConfigStore::new()?;
+    let (profile, credentials) = profile_credentials(opt, profile_name, aws_config.clone())?;
+
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| format_err!("No command given"))?;
+
+    let mut child = process::Command::new(program);
+    child
+        .args(args)
+        .envs(env_variables(&profile, &credentials, &aws_config)?);
+
+    // The command itself would show the credentials in its environment
+    debug!("Running {} {:?}", program, args);
+
+    run_child(child, program)
+}
+
+/// The environment variables for a profile, which is only named if the AWS CLI knows it
+fn env_variables(
     profile: &Profile,
-) -> Result<Credentials, Error> {
-    info!(
-        "Requesting tokens for {}/{}",
-        &organization.okta_organization.name, profile.name
+    credentials: &ProcessCredentials,
+    aws_config: &ConfigStore,
+) -> Result<Vec<(&'static str, String)>, Error> {
+    let known_profile = aws_config.has_profile(&profile.name)
+        || CredentialsStore::new()?
+            .get_profile(&profile.name)
+            .is_some();
+
+    Ok(aws_env::variables(
+        credentials,
+        if known_profile {
+            Some(&profile.name)
+        } else {
+            None
+        },
+        profile.region(),
+    ))
+}
+
+// On Unix the command replaces oktaws, so that it gets signals and its exit code is ours
+#[cfg(unix)]
+fn run_child(mut child: process::Command, program: &str) -> Result<(), Error> {
+    use std::os::unix::process::CommandExt;
+
+    let error = child.exec();
+    Err(error)
+        .with_context(|_| format!("Could not run {}", program))
+        .map_err(|e| e.into())
+}
+
+#[cfg(not(unix))]
+fn run_child(mut child: process::Command, program: &str) -> Result<(), Error> {
+    let status = child
+        .status()
+        .with_context(|_| format!("Could not run {}", program))?;
+
+    process::exit(status.code().unwrap_or(1))
+}
+
+/// Asks for a profile name for every AWS role available in Okta, and saves them
+fn init(opt: &Opt, organization_name: &str) -> Result<(), Error> {
+    let existing_organization = Config::new()?
+        .organizations()
+        .find(|o| o.okta_organization.name == organization_name);
+
+    let organization = match existing_organization {
+        Some(organization) => organization,
+        None => {
+            let okta_organization = organization_name
+                .parse::<OktaOrganization>()
+                .context(ErrorKind::Config)?;
+
+            Organization {
+                path: oktaws_home()?.join(format!("{}.toml", organization_name)),
+                username: credentials::get_username(&okta_organization)?,
+                okta_organization,
+                factor: None,
+                profiles: Vec::new(),
+            }
+        }
+    };
+
+    let session = login(opt, organization.clone())?;
+
+    let mut profiles = Vec::new();
+    for application in session.aws_applications()? {
+        for role in &application.roles {
+            let role_name = role.role_name()?;
+
+            let existing_profile = organization.profiles.iter().find(|p| {
+                p.application_name == application.name
+                    && p.role.as_ref().map(|r| r.as_str()) == Some(role_name)
+            });
+
+            if let Some(profile) = existing_profile {
+                info!(
+                    "{} / {} is already profile {}",
+                    application.name, role_name, profile.name
+                );
+                continue;
+            }
+
+            let long_name = profile_name(&[application.name.as_str(), role_name]);
+            let mut default_name = if application.roles.len() == 1 {
+                profile_name(&[application.name.as_str()])
+            } else {
+                long_name.clone()
+            };
+            let mut suffix = 2;
+            while profile_name_taken(&default_name, &organization.profiles, &profiles) {
+                default_name = format!("{}-{}", long_name, suffix);
+                suffix += 1;
+            }
+
+            let name = loop {
+                let mut input = Input::new(&format!(
+                    "Profile for {} / {} in account {} (- to skip)",
+                    application.name,
+                    role_name,
+                    role.account_id()?
+                ));
+                input.default(&default_name);
+                let name = input.interact()?;
+
+                if name != "-" && profile_name_taken(&name, &organization.profiles, &profiles) {
+                    warn!("Profile {} is already used, pick another name", name);
+                } else {
+                    break name;
+                }
+            };
+
+            if name != "-" {
+                profiles.push(Profile::new(
+                    name,
+                    application.name.clone(),
+                    Some(role_name.to_owned()),
+                ));
+            }
+        }
+    }
+
+    if profiles.is_empty() {
+        info!("No new profiles for {}", organization_name);
+        return Ok(());
+    }
+
+    organization
+        .save_profiles(&profiles)
+        .context(ErrorKind::Config)
+        .map_err(|e| e.into())
+}
+
+/// Prints the matching organizations and profiles, and optionally what they resolve to in Okta
+fn list(opt: &Opt, remote: bool) -> Result<(), Error> {
+    let organizations = Config::new()?
+        .organizations()
+        .filter(|o| opt.organizations.matches(&o.okta_organization.name));
+
+    for organization in organizations {
+        println!(
+            "{} ({})",
+            organization.okta_organization.name,
+            organization.path.display()
+        );
+
+        for profile in &organization.profiles {
+            println!(
+                "  {}: {} / {}{}",
+                profile.name,
+                profile.application_name,
+                profile
+                    .role
+                    .as_ref()
+                    .map(|r| r.as_str())
+                    .unwrap_or("<no role>"),
+                profile
+                    .duration_seconds
+                    .map(|d| format!(" ({}s)", d))
+                    .unwrap_or_default()
+            );
+        }
+
+        if !remote {
+            continue;
+        }
+
+        let session = login(opt, organization.clone())?;
+        let applications = session.aws_applications()?;
+
+        let resolves = |profile: &Profile, application: &AwsApplication, role: &Role| {
+            profile.application_name == application.name
+                && profile.role.as_ref().map(|r| r.as_str()) == role.role_name().ok()
+        };
+
+        println!("  Available in Okta:");
+        for application in &applications {
+            println!("    {}", application.name);
+
+            for role in &application.roles {
+                let profiles = organization
+                    .profiles
+                    .iter()
+                    .filter(|p| resolves(p, application, role))
+                    .map(|p| p.name.as_str())
+                    .collect::<Vec<&str>>();
+
+                println!(
+                    "      {} / {}{}",
+                    role.account_id().unwrap_or("?"),
+                    role.role_name().unwrap_or(role.role_arn.as_str()),
+                    if profiles.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" [{}]", profiles.join(", "))
+                    }
+                );
+            }
+        }
+
+        let unresolved = organization.profiles.iter().filter(|p| {
+            !applications
+                .iter()
+                .any(|a| a.roles.iter().any(|r| resolves(p, a, r)))
+        });
+
+        for profile in unresolved {
+            println!(
+                "  ! {} does not resolve to any role available in Okta",
+                profile.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the matching profiles of the AWS credentials file which oktaws wrote
+fn status(opt: &Opt, json: bool) -> Result<(), Error> {
+    let credentials_store = CredentialsStore::new()?;
+    let configured_profiles = configured_profiles(
+        Config::new()?
+            .organizations()
+            .filter(|o| opt.organizations.matches(&o.okta_organization.name)),
     );
 
-    let app_link = client
-        .app_links(None)?
+    let statuses = oktaws_profiles(&credentials_store, &configured_profiles)
         .into_iter()
-        .find(|app_link| {
-            app_link.app_name == "amazon_aws" && app_link.label == profile.application_name
+        .filter(|&(ref name, _)| opt.profiles.matches(name))
+        .map(|(name, credentials)| {
+            let role_arn = credentials.role_arn();
+            let role_name = role_arn
+                .and_then(|arn| role::role_name(arn).ok())
+                .or_else(|| {
+                    configured_profiles
+                        .get(&name)
+                        .and_then(|p| p.role.as_ref().map(|r| r.as_str()))
+                })
+                .map(|r| r.to_owned());
+
+            ProfileStatus {
+                account_id: role_arn
+                    .and_then(|arn| role::account_id(arn).ok())
+                    .map(|a| a.to_owned()),
+                role_name,
+                expiration: credentials.expiration(),
+                name,
+            }
         })
-        .ok_or_else(|| {
-            format_err!(
-                "Could not find Okta application for profile {}/{}",
-                organization.okta_organization.name,
-                profile.name
-            )
-        })?;
+        .collect::<Vec<ProfileStatus>>();
+
+    if json {
+        let statuses = statuses
+            .iter()
+            .map(|status| {
+                json!({
+                    "profile": status.name,
+                    "account_id": status.account_id,
+                    "role": status.role_name,
+                    "expiration": status.expiration.map(|e| e.to_rfc3339()),
+                    "remaining_seconds": status.remaining().map(|r| r.num_seconds().max(0)),
+                    "expired": status.is_expired(),
+                })
+            })
+            .collect::<Vec<_>>();
 
-    debug!("Application Link: {:?}", &app_link);
+        println!("{}", serde_json::to_string_pretty(&statuses)?);
+        return Ok(());
+    }
 
-    let saml = client.get_saml_response(app_link.link_url).map_err(|e| {
-        format_err!(
-            "Error getting SAML response for profile {} ({})",
-            profile.name,
-            e
-        )
-    })?;
+    let width = statuses.iter().map(|s| s.name.len()).max().unwrap_or(0);
+
+    for status in &statuses {
+        let lifetime = match status.remaining() {
+            Some(remaining) if remaining <= chrono::Duration::zero() => {
+                format!("expired {} ago", format_duration(-remaining))
+            }
+            Some(remaining) => format!("expires in {}", format_duration(remaining)),
+            None => String::from("unknown expiration"),
+        };
+
+        println!(
+            "{:width$}  {} / {}  {}",
+            status.name,
+            status
+                .account_id
+                .as_ref()
+                .map(|a| a.as_str())
+                .unwrap_or("?"),
+            status.role_name.as_ref().map(|r| r.as_str()).unwrap_or("?"),
+            lifetime,
+            width = width
+        );
+    }
+
+    Ok(())
+}
 
-    trace!("SAML response: {:?}", saml);
+struct ProfileStatus {
+    name: String,
+    account_id: Option<String>,
+    role_name: Option<String>,
+    expiration: Option<DateTime<Utc>>,
+}
 
-    let roles = saml.roles;
+impl ProfileStatus {
+    fn remaining(&self) -> Option<chrono::Duration> {
+        self.expiration.map(|expiration| expiration - Utc::now())
+    }
 
-    debug!("SAML Roles: {:?}", &roles);
+    fn is_expired(&self) -> bool {
+        self.remaining()
+            .map(|remaining| remaining <= chrono::Duration::zero())
+            .unwrap_or(false)
+    }
+}
 
-    let role: Role = roles
+/// Removes the matching profiles of the AWS credentials file which oktaws wrote and are stale
+fn clean(opt: &Opt, dry_run: bool) -> Result<(), Error> {
+    let config = Config::new()?;
+    let remove_unconfigured = unconfigured_removable(opt, &config);
+    let organizations = config
+        .organizations()
+        .filter(|o| opt.organizations.matches(&o.okta_organization.name))
+        .collect::<Vec<Organization>>();
+
+    // Without organizations, every profile would look like it is no longer configured
+    if organizations.is_empty() {
+        return Err(format_err!("No organizations found")
+            .context(ErrorKind::Config)
+            .into());
+    }
+
+    let configured_profiles = configured_profiles(organizations.into_iter());
+    let mut credentials_store = CredentialsStore::new()?;
+
+    for (name, reason) in stale_profiles(
+        opt,
+        &credentials_store,
+        &configured_profiles,
+        remove_unconfigured,
+    ) {
+        if dry_run {
+            println!("Would remove {} ({})", name, reason);
+        } else {
+            println!("Removing {} ({})", name, reason);
+        }
+
+        credentials_store.remove_profile(name)?;
+    }
+
+    if dry_run {
+        Ok(())
+    } else {
+        credentials_store.save()
+    }
+}
+
+fn configured_profiles(
+    organizations: impl Iterator<Item = Organization>,
+) -> HashMap<String, Profile> {
+    organizations
+        .flat_map(|o| o.profiles)
+        .map(|p| (p.name.clone(), p))
+        .collect()
+}
+
+/// The profiles of the AWS credentials file with STS credentials written by oktaws.
+///
+/// Those written before oktaws recorded their role are recognised by being configured.
+fn oktaws_profiles(
+    credentials_store: &CredentialsStore,
+    configured_profiles: &HashMap<String, Profile>,
+) -> Vec<(String, ProfileCredentials)> {
+    credentials_store
+        .profiles()
         .into_iter()
-        .find(|r| r.role_name().map(|r| r == profile.role).unwrap_or(false))
-        .ok_or_else(|| {
-            format_err!(
-                "No matching role ({}) found for profile {}",
-                profile.role,
-                &profile.name
-            )
-        })?;
-
-    trace!(
-        "Found role: {} for profile {}",
-        role.role_arn,
-        &profile.name
-    );
+        .filter(|&(ref name, ref credentials)| match *credentials {
+            ProfileCredentials::Sts { .. } => {
+                credentials.role_arn().is_some() || configured_profiles.contains_key(name)
+            }
+            ProfileCredentials::Iam { .. } => false,
+        })
+        .collect()
+}
+
+/// Whether profiles which are in no organization can be told apart from those of other organizations
+fn unconfigured_removable(opt: &Opt, config: &Config) -> bool {
+    if !config.fully_loaded() {
+        warn!("Some organization files could not be read, only removing expired profiles");
+        false
+    } else if opt.organizations.as_str() != "*" {
+        info!("Only removing expired profiles of the matching organizations");
+        false
+    } else {
+        true
+    }
+}
+
+/// The matching oktaws profiles which have expired or are no longer configured, and why.
+///
+/// Only those with their role recorded, as a profile made by hand can share a configured name.
+fn stale_profiles(
+    opt: &Opt,
+    credentials_store: &CredentialsStore,
+    configured_profiles: &HashMap<String, Profile>,
+    remove_unconfigured: bool,
+) -> Vec<(String, &'static str)> {
+    oktaws_profiles(credentials_store, configured_profiles)
+        .into_iter()
+        .filter(|&(ref name, ref credentials)| {
+            opt.profiles.matches(name) && credentials.role_arn().is_some()
+        })
+        .filter_map(|(name, credentials)| {
+            if !configured_profiles.contains_key(&name) {
+                if remove_unconfigured {
+                    Some((name, "no longer configured"))
+                } else {
+                    None
+                }
+            } else if credentials
+                .expiration()
+                .map_or(false, |expiration| expiration <= Utc::now())
+            {
+                Some((name, "expired"))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// A rough duration such as `1h 5m`
+fn format_duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds();
+
+    match (seconds / 3600, seconds % 3600 / 60) {
+        (0, 0) => format!("{}s", seconds),
+        (0, minutes) => format!("{}m", minutes),
+        (hours, minutes) => format!("{}h {}m", hours, minutes),
+    }
+}
+
+/// Whether a profile name is used by an existing profile or one entered earlier
+fn profile_name_taken(name: &str, existing: &[Profile], entered: &[Profile]) -> bool {
+    existing.iter().chain(entered).any(|p| p.name == name)
+}
+
+/// A profile name such as `my-app-admin`
+fn profile_name(parts: &[&str]) -> String {
+    parts
+        .iter()
+        .flat_map(|part| part.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+/// Gets the credentials of a single profile, from the keyring while they are still valid
+fn profile_credentials(
+    opt: &Opt,
+    profile_name: &str,
+    aws_config: ConfigStore,
+) -> Result<(Profile, ProcessCredentials), Error> {
+    let (organization, profile) = find_profile(opt, profile_name)?;
+    let refresh_margin = chrono::Duration::seconds(opt.refresh_margin);
+
+    let cached_credentials = if opt.force_new {
+        None
+    } else {
+        credentials::get_process_credentials(&organization.okta_organization, &profile.name)
+            .filter(|credentials| credentials.is_valid_for(refresh_margin))
+    };
 
-    let assumption_response = aws::role::assume_role(role, saml.raw, profile.duration_seconds)
-        .map_err(|e| format_err!("Error assuming role for profile {} ({})", profile.name, e))?;
+    let process_credentials = match cached_credentials {
+        Some(credentials) => {
+            info!("Using cached credentials for {}", profile.name);
+            credentials
+        }
+        None => {
+            let session = builder(opt, organization).aws_config(aws_config).login()?;
+            let credentials = ProcessCredentials::from(session.credentials(&profile)?);
+
+            if let Err(e) = credentials::save_process_credentials(
+                &session.organization().okta_organization,
+                &profile.name,
+                &credentials,
+            ) {
+                warn!("Could not cache credentials for {} ({})", profile.name, e);
+            }
+
+            credentials
+        }
+    };
 
-    let credentials = assumption_response
-        .credentials
-        .ok_or_else(|| format_err!("Error fetching credentials from assumed AWS role"))?;
+    Ok((profile, process_credentials))
+}
+
+fn login(opt: &Opt, organization: Organization) -> Result<Session, Error> {
+    builder(opt, organization).login()
+}
 
-    trace!("Credentials: {:?}", credentials);
+fn builder(opt: &Opt, organization: Organization) -> Builder {
+    Builder::new(organization)
+        .password_provider(KeyringPasswordProvider {
+            force_new: opt.force_new,
+        })
+        .mfa_timeout(Duration::from_secs(opt.mfa_timeout))
+        // Menus can't be answered by scripts, nor shared by profiles fetched in parallel
+        .interactive(!opt.asynchronous && atty::is(Stream::Stdin) && atty::is(Stream::Stderr))
+}
 
-    Ok(credentials)
+/// Finds a profile by its exact name, in the first matching organization which has it
+fn find_profile(opt: &Opt, profile_name: &str) -> Result<(Organization, Profile), Error> {
+    Config::new()?
+        .organizations()
+        .filter(|o| opt.organizations.matches(&o.okta_organization.name))
+        .filter_map(|organization| {
+            organization
+                .profiles
+                .iter()
+                .find(|p| p.name == profile_name)
+                .cloned()
+                .map(|profile| (organization.clone(), profile))
+        })
+        .next()
+        .ok_or_else(|| format_err!("No profile found called {}", profile_name))
+        .context(ErrorKind::Config)
+        .map_err(|e| e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::NamedTempFile;
+    use try_from::TryInto;
+
+    fn credentials(name: &str, expiration: &str) -> String {
+        legacy_credentials(name, expiration)
+            + "oktaws_role_arn=arn:aws:iam::123456789012:role/admin\n"
+    }
+
+    fn legacy_credentials(name: &str, expiration: &str) -> String {
+        format!(
+            "[{}]
+aws_access_key_id=ACCESS_KEY
+aws_secret_access_key=SECRET_ACCESS_KEY
+aws_session_token=SESSION_TOKEN
+aws_expiration={}
+",
+            name, expiration
+        )
+    }
+
+    #[test]
+    fn stale_profiles_keep_unconfigured() {
+        let tmpfile = NamedTempFile::new().unwrap();
+        fs::write(
+            tmpfile.path(),
+            credentials("configured", "2000-01-01T00:00:00Z")
+                + &credentials("unconfigured", "2100-01-01T00:00:00Z"),
+        )
+        .unwrap();
+        let credentials_store: CredentialsStore = tmpfile.path().try_into().unwrap();
+
+        let mut configured = HashMap::new();
+        configured.insert(
+            String::from("configured"),
+            Profile::new(String::from("configured"), String::from("App"), None),
+        );
+
+        let opt = Opt::from_iter(&["oktaws", "clean"]);
+
+        assert_eq!(
+            stale_profiles(&opt, &credentials_store, &configured, true),
+            vec![
+                (String::from("configured"), "expired"),
+                (String::from("unconfigured"), "no longer configured"),
+            ]
+        );
+        assert_eq!(
+            stale_profiles(&opt, &credentials_store, &configured, false),
+            vec![(String::from("configured"), "expired")]
+        );
+    }
+
+    #[test]
+    fn stale_profiles_keep_unmarked() {
+        let tmpfile = NamedTempFile::new().unwrap();
+        fs::write(
+            tmpfile.path(),
+            legacy_credentials("configured", "2000-01-01T00:00:00Z"),
+        )
+        .unwrap();
+        let credentials_store: CredentialsStore = tmpfile.path().try_into().unwrap();
+
+        let mut configured = HashMap::new();
+        configured.insert(
+            String::from("configured"),
+            Profile::new(String::from("configured"), String::from("App"), None),
+        );
+
+        let opt = Opt::from_iter(&["oktaws", "clean"]);
+
+        assert_eq!(oktaws_profiles(&credentials_store, &configured).len(), 1);
+        assert!(stale_profiles(&opt, &credentials_store, &configured, true).is_empty());
+    }
 }
@@ -1,12 +1,18 @@
 use dialoguer;
-use dialoguer::Input;
-use failure::Error;
+use dialoguer::Confirmation;
+use failure::{Error, ResultExt};
 use std::collections::HashMap;
 
+use error::ErrorKind;
+
 use okta::client::Client;
-use okta::factors::{Factor, FactorVerificationRequest};
+use okta::duo::DuoVerification;
+use okta::factors::{Factor, FactorEnrollment, FactorEnrollments, FactorProvider};
+use okta::prompt_hidden;
 use okta::users::User;
 use okta::Links;
+use okta::Links::{Multi, Single};
+use reqwest::Url;
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -55,10 +61,11 @@ struct Options {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct LoginResponse {
-    state_token: Option<String>,
+    pub state_token: Option<String>,
     pub session_token: Option<String>,
     expires_at: String,
-    status: LoginState,
+    pub status: LoginState,
+    pub factor_result: Option<FactorResult>,
     relay_state: Option<String>,
     #[serde(rename = "_embedded")]
     embedded: Option<LoginEmbedded>,
@@ -70,11 +77,74 @@ pub struct LoginResponse {
 #[serde(rename_all = "camelCase")]
 pub struct LoginEmbedded {
     #[serde(default)]
-    factors: Vec<Factor>,
-    user: User,
+    factors: Vec<EmbeddedFactor>,
+    factor: Option<Factor>,
+    user: Option<User>,
 }
 
+// Factors that still need to be enrolled have no id or profile yet
 #[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum EmbeddedFactor {
+    Enrolled(Factor),
+    Enrollable(FactorEnrollment),
+}
+
+impl LoginEmbedded {
+    fn enrolled_factors(self) -> Vec<Factor> {
+        self.factors
+            .into_iter()
+            .filter_map(|factor| match factor {
+                EmbeddedFactor::Enrolled(factor) => Some(factor),
+                EmbeddedFactor::Enrollable(_) => None,
+            })
+            .collect()
+    }
+
+    fn enrollable_factors(self) -> Vec<FactorEnrollment> {
+        self.factors
+            .into_iter()
+            .filter_map(|factor| match factor {
+                EmbeddedFactor::Enrolled(_) => None,
+                EmbeddedFactor::Enrollable(factor) => Some(factor),
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangePasswordRequest {
+    state_token: String,
+    old_password: String,
+    new_password: String,
+}
+
+#[derive(Debug)]
+pub struct Authentication {
+    pub session_token: String,
+    /// Set when an expired password was changed while logging in
+    pub new_password: Option<String>,
+}
+
+#[derive(Fail, Debug)]
+pub enum LoginError {
+    #[fail(display = "Okta password has expired, change it in Okta and try again")]
+    PasswordExpired,
+    #[fail(
+        display = "Okta account is locked out, unlock it at {} or ask your Okta administrator",
+        _0
+    )]
+    LockedOut(Url),
+    #[fail(display = "MFA enrollment required, enroll in Okta first ({})", _0)]
+    MfaEnroll(FactorEnrollments),
+    #[fail(display = "MFA challenge was not completed")]
+    MfaChallenge,
+    #[fail(display = "Unsupported login state {:?}", _0)]
+    Unsupported(LoginState),
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum LoginState {
     Unauthenticated,
@@ -91,6 +161,57 @@ pub enum LoginState {
     Success,
 }
 
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FactorResult {
+    Success,
+    Challenge,
+    Waiting,
+    Failed,
+    Rejected,
+    Timeout,
+    TimeWindowExceeded,
+    PasscodeReplayed,
+    Error,
+    Cancelled,
+}
+
+impl LoginResponse {
+    pub fn poll_url(&self) -> Option<Url> {
+        self.links
+            .get("poll")
+            .or_else(|| self.links.get("next"))
+            .and_then(|links| match *links {
+                Single(ref link) => Some(link.href.clone()),
+                Multi(ref links) => links
+                    .iter()
+                    .find(|link| link.name.as_ref().map(|n| n == "poll").unwrap_or(false))
+                    .map(|link| link.href.clone()),
+            })
+    }
+
+    pub fn duo_verification(&self) -> Option<&DuoVerification> {
+        match self.embedded.as_ref().and_then(|e| e.factor.as_ref()) {
+            Some(&Factor::Web {
+                embedded: Some(ref embedded),
+                ..
+            }) => embedded.verification.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn resend_url(&self) -> Option<Url> {
+        self.link_url("resend")
+    }
+
+    fn link_url(&self, name: &str) -> Option<Url> {
+        self.links.get(name).and_then(|links| match *links {
+            Single(ref link) => Some(link.href.clone()),
+            Multi(ref links) => links.first().map(|link| link.href.clone()),
+        })
+    }
+}
+
 impl Client {
     pub fn login(&self, req: &LoginRequest) -> Result<LoginResponse, Error> {
         let login_type = if req.state_token.is_some() {
@@ -102,75 +223,264 @@ impl Client {
         debug!("Attempting to login with {}", login_type);
 
         self.post("api/v1/authn", req)
+            .context(ErrorKind::OktaAuth)
+            .map_err(|e| e.into())
     }
 
     pub fn get_session_token(&self, req: &LoginRequest) -> Result<String, Error> {
-        let response = self.login(req)?;
+        self.authenticate(req)
+            .map(|authentication| authentication.session_token)
+    }
 
-        trace!("Login response: {:?}", response);
+    pub fn authenticate(&self, req: &LoginRequest) -> Result<Authentication, Error> {
+        let mut response = self.login(req)?;
+        let mut new_password = None;
 
-        match response.status {
-            LoginState::Success => Ok(response.session_token.unwrap()),
-            LoginState::MfaRequired => {
-                info!("MFA required");
+        loop {
+            trace!("Login response: {:?}", response);
 
-                let factors = response.embedded.unwrap().factors;
+            response = match response.status {
+                LoginState::Success => {
+                    return Ok(Authentication {
+                        session_token: response
+                            .session_token
+                            .ok_or_else(|| format_err!("No session token found in response"))?,
+                        new_password,
+                    })
+                }
+                LoginState::MfaRequired => self.verify_mfa(response).context(ErrorKind::Mfa)?,
+                LoginState::PasswordWarn => {
+                    warn!("Your Okta password is about to expire, change it soon");
+                    self.follow_link(&response, "skip")?
+                }
+                LoginState::PasswordExpired => {
+                    let old_password = req.password.clone().ok_or(LoginError::PasswordExpired)?;
+                    let (response, password) = self.change_password(response, old_password)?;
+                    new_password = Some(password);
+                    response
+                }
+                LoginState::LockedOut => {
+                    return Err(
+                        LoginError::LockedOut(self.base_url().join("signin/unlock")?).into(),
+                    )
+                }
+                LoginState::MfaEnroll => {
+                    let factors = response
+                        .embedded
+                        .map(|e| e.enrollable_factors())
+                        .unwrap_or_default();
+                    return Err(LoginError::MfaEnroll(FactorEnrollments(factors)).into());
+                }
+                LoginState::MfaChallenge => return Err(LoginError::MfaChallenge.into()),
+                state => return Err(LoginError::Unsupported(state).into()),
+            }
+        }
+    }
 
-                let factor = match factors.len() {
-                    0 => bail!("MFA required, and no available factors"),
-                    1 => {
-                        info!("Only one factor available, using it");
-                        &factors[0]
-                    }
-                    _ => {
-                        let mut menu = dialoguer::Select::new();
-                        for factor in &factors {
-                            menu.item(&factor.to_string());
-                        }
-                        &factors[menu.interact()?]
-                    }
-                };
+    fn verify_mfa(&self, response: LoginResponse) -> Result<LoginResponse, Error> {
+        info!("MFA required");
 
-                debug!("Factor: {:?}", factor);
+        let factors = response
+            .embedded
+            .map(|e| e.enrolled_factors())
+            .unwrap_or_default();
 
-                let state_token = response
+        let preferred_factor = self
+            .factor_selector()
+            .and_then(|selector| factors.iter().find(|f| f.matches(selector)));
+
+        if preferred_factor.is_none() && self.factor_selector().is_some() {
+            warn!("No enrolled factor matches the configured factor");
+        }
+
+        let factor = match (preferred_factor, factors.len()) {
+            (Some(factor), _) => {
+                info!("Using configured factor {}", factor);
+                factor
+            }
+            (None, 0) => bail!("MFA required, and no available factors"),
+            (None, 1) => {
+                info!("Only one factor available, using it");
+                &factors[0]
+            }
+            (None, _) => {
+                let mut menu = dialoguer::Select::new();
+                for factor in &factors {
+                    menu.item(&factor.to_string());
+                }
+                &factors[menu.interact()?]
+            }
+        };
+
+        debug!("Factor: {:?}", factor);
+
+        let state_token = response
+            .state_token
+            .ok_or_else(|| format_err!("No state token found in response"))?;
+
+        let factor_response = match *factor {
+            Factor::Sms { .. } => self.verify_sms(&factor, state_token)?,
+            Factor::Totp { .. } => self.verify_totp(&factor, state_token)?,
+            Factor::Push { .. } => self.verify_push(&factor, state_token)?,
+            Factor::Question { .. } => self.verify_question(&factor, state_token)?,
+            Factor::Call { .. } => self.verify_call(&factor, state_token)?,
+            Factor::Web {
+                provider: FactorProvider::Duo,
+                ..
+            } => self.verify_duo(&factor, state_token)?,
+            Factor::Token { .. } | Factor::Hotp { .. } => {
+                self.verify_token(&factor, state_token)?
+            }
+            _ => bail!("Unsupported MFA method"),
+        };
+
+        trace!("Factor Response: {:?}", factor_response);
+
+        Ok(factor_response)
+    }
+
+    fn change_password(
+        &self,
+        response: LoginResponse,
+        old_password: String,
+    ) -> Result<(LoginResponse, String), Error> {
+        if !Confirmation::new("Your Okta password has expired, change it now?").interact()? {
+            return Err(LoginError::PasswordExpired.into());
+        }
+
+        let new_password = loop {
+            let new_password = prompt_hidden("New password")?;
+
+            if new_password == prompt_hidden("Confirm new password")? {
+                break new_password;
+            }
+
+            warn!("Passwords do not match, please try again");
+        };
+
+        let url = response
+            .link_url("next")
+            .ok_or_else(|| format_err!("No changePassword link found in response"))?;
+
+        let response = self.post_absolute(
+            url,
+            &ChangePasswordRequest {
+                state_token: response
                     .state_token
-                    .ok_or_else(|| format_err!("No state token found in response"))?;
+                    .ok_or_else(|| format_err!("No state token found in response"))?,
+                old_password,
+                new_password: new_password.clone(),
+            },
+        )?;
+
+        info!("Okta password changed");
 
-                let factor_prompt_response = self.verify(
-                    &factor,
-                    &FactorVerificationRequest::Sms {
-                        state_token,
-                        pass_code: None,
-                    },
-                )?;
+        Ok((response, new_password))
+    }
 
-                trace!("Factor Prompt Response: {:?}", factor_prompt_response);
+    fn follow_link(&self, response: &LoginResponse, name: &str) -> Result<LoginResponse, Error> {
+        let url = response
+            .link_url(name)
+            .ok_or_else(|| format_err!("No {} link found in response", name))?;
 
-                let state_token = factor_prompt_response
+        self.post_absolute(
+            url,
+            &LoginRequest::from_state_token(
+                response
                     .state_token
-                    .ok_or_else(|| format_err!("No state token found in factor prompt response"))?;
+                    .clone()
+                    .ok_or_else(|| format_err!("No state token found in response"))?,
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
 
-                let input = Input::new("MFA response");
+    #[test]
+    fn parse_push_waiting() {
+        let response: LoginResponse = serde_json::from_str(
+            r#"{
+                "stateToken": "STATE_TOKEN",
+                "expiresAt": "2015-11-03T10:15:57.000Z",
+                "status": "MFA_CHALLENGE",
+                "factorResult": "WAITING",
+                "_links": {
+                    "next": {
+                        "name": "poll",
+                        "href": "https://example.okta.com/api/v1/authn/factors/FACTOR_ID/verify",
+                        "hints": { "allow": ["POST"] }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
 
-                let mfa_code = input.interact()?;
+        assert_eq!(response.status, LoginState::MfaChallenge);
+        assert_eq!(response.factor_result, Some(FactorResult::Waiting));
+        assert_eq!(
+            response.poll_url().unwrap().as_str(),
+            "https://example.okta.com/api/v1/authn/factors/FACTOR_ID/verify"
+        );
+    }
 
-                let factor_provided_response = self.verify(
-                    &factor,
-                    &FactorVerificationRequest::Sms {
-                        state_token,
-                        pass_code: Some(mfa_code),
-                    },
-                )?;
+    #[test]
+    fn parse_call_challenge() {
+        let response: LoginResponse = serde_json::from_str(
+            r#"{
+                "stateToken": "STATE_TOKEN",
+                "expiresAt": "2015-11-03T10:15:57.000Z",
+                "status": "MFA_CHALLENGE",
+                "_links": {
+                    "resend": [
+                        {
+                            "name": "call",
+                            "href": "https://example.okta.com/api/v1/authn/factors/FACTOR_ID/verify/resend",
+                            "hints": { "allow": ["POST"] }
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
 
-                trace!("Factor Provided Response: {:?}", factor_provided_response);
+        assert_eq!(response.factor_result, None);
+        assert_eq!(
+            response.resend_url().unwrap().as_str(),
+            "https://example.okta.com/api/v1/authn/factors/FACTOR_ID/verify/resend"
+        );
+    }
 
-                Ok(factor_provided_response.session_token.unwrap())
-            }
-            _ => {
-                println!("Resp: {:?}", response);
-                bail!("Non MFA")
-            }
-        }
+    #[test]
+    fn parse_mfa_enroll() {
+        let response: LoginResponse = serde_json::from_str(
+            r#"{
+                "stateToken": "STATE_TOKEN",
+                "expiresAt": "2015-11-03T10:15:57.000Z",
+                "status": "MFA_ENROLL",
+                "_embedded": {
+                    "factors": [
+                        {
+                            "factorType": "push",
+                            "provider": "OKTA",
+                            "enrollment": "REQUIRED",
+                            "status": "NOT_SETUP"
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let factors = response.embedded.unwrap().enrollable_factors();
+
+        assert_eq!(
+            LoginError::MfaEnroll(FactorEnrollments(factors)).to_string(),
+            "MFA enrollment required, enroll in Okta first (OKTA push (required))"
+        );
     }
 }
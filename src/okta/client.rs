@@ -7,13 +7,17 @@ use reqwest::Url;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::time::Duration;
 
+use okta::factors::FactorSelector;
 use okta::Organization;
 
 pub struct Client {
     client: HttpClient,
     organization: Organization,
     cookies: HashMap<String, String>,
+    mfa_timeout: Duration,
+    factor_selector: Option<FactorSelector>,
 }
 
 impl Client {
@@ -22,13 +26,35 @@ impl Client {
             client: HttpClient::new(),
             organization,
             cookies: HashMap::new(),
+            mfa_timeout: Duration::from_secs(60),
+            factor_selector: None,
         }
     }
 
+    pub fn set_mfa_timeout(&mut self, mfa_timeout: Duration) {
+        self.mfa_timeout = mfa_timeout;
+    }
+
+    pub fn mfa_timeout(&self) -> Duration {
+        self.mfa_timeout
+    }
+
+    pub fn set_factor_selector(&mut self, factor_selector: Option<FactorSelector>) {
+        self.factor_selector = factor_selector;
+    }
+
+    pub fn factor_selector(&self) -> Option<&FactorSelector> {
+        self.factor_selector.as_ref()
+    }
+
     pub fn set_session_id(&mut self, session_id: String) {
         self.cookies.insert("sid".to_string(), session_id);
     }
 
+    pub fn base_url(&self) -> &Url {
+        &self.organization.base_url
+    }
+
     fn cookie_header(&self) -> String {
         self.cookies
             .iter()
@@ -90,4 +116,17 @@ impl Client {
             .json()
             .map_err(|e| e.into())
     }
+
+    /// Posts a form to a URL that is not necessarily Okta, so no session cookies are sent
+    pub fn post_form<I>(&self, url: Url, body: &I) -> Result<Response, Error>
+    where
+        I: Serialize + ?Sized,
+    {
+        self.client
+            .post(url)
+            .form(body)
+            .send()?
+            .error_for_status()
+            .map_err(|e| e.into())
+    }
 }
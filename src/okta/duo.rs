@@ -0,0 +1,648 @@
+use dialoguer::{Input, Select};
+use failure::Error;
+use kuchiki;
+use kuchiki::traits::TendrilSink;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use okta::auth::LoginResponse;
+use okta::client::Client;
+use okta::factors::{Factor, FactorVerificationRequest};
+use okta::Links;
+use okta::Links::{Multi, Single};
+
+const DUO_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The parameters Okta hands out for the Duo iframe
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DuoVerification {
+    host: String,
+    signature: String,
+    #[serde(rename = "_links", default)]
+    links: HashMap<String, Links>,
+}
+
+impl DuoVerification {
+    /// Duo signatures are of the form `TX|...:APP|...`
+    fn signatures(&self) -> Result<(&str, &str), Error> {
+        let mut splitted = self.signature.splitn(2, ':');
+
+        match (splitted.next(), splitted.next()) {
+            (Some(tx), Some(app)) => Ok((tx, app)),
+            _ => bail!("Invalid Duo signature {}", self.signature),
+        }
+    }
+
+    fn base_url(&self) -> Result<Url, Error> {
+        Url::parse(&format!("https://{}/", self.host)).map_err(|e| e.into())
+    }
+
+    fn callback_url(&self) -> Result<Url, Error> {
+        match self.links.get("complete") {
+            Some(&Single(ref link)) => Ok(link.href.clone()),
+            Some(&Multi(ref links)) if !links.is_empty() => Ok(links[0].href.clone()),
+            _ => bail!("No Duo callback link found"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DuoResponse<T> {
+    stat: String,
+    response: Option<T>,
+    message: Option<String>,
+}
+
+impl<T> DuoResponse<T> {
+    fn into_result(self) -> Result<T, Error> {
+        let DuoResponse {
+            stat,
+            response,
+            message,
+        } = self;
+
+        match (stat.as_str(), response) {
+            ("OK", Some(response)) => Ok(response),
+            _ => bail!(
+                "Duo request failed ({})",
+                message.unwrap_or_else(|| stat.clone())
+            ),
+        }
+    }
+}
+
+/// The Duo prompt frame, with the devices it offers
+#[derive(Debug, PartialEq)]
+struct DuoFrame {
+    sid: String,
+    devices: Vec<DuoDevice>,
+}
+
+#[derive(Debug, PartialEq)]
+struct DuoDevice {
+    id: String,
+    name: String,
+}
+
+impl DuoFrame {
+    fn parse(url: &Url, html: &str) -> Result<DuoFrame, Error> {
+        let doc = kuchiki::parse_html().one(html);
+
+        // Duo either redirects to the prompt with the sid, or embeds it in a form
+        let sid = match url.query_pairs().find(|&(ref k, _)| k == "sid") {
+            Some((_, sid)) => sid.into_owned(),
+            None => {
+                let input_node = doc
+                    .select_first("input[name='sid']")
+                    .map_err(|_| format_err!("No Duo sid found"))?;
+                let attributes = input_node.attributes.borrow();
+
+                attributes
+                    .get("value")
+                    .map(|sid| sid.to_owned())
+                    .ok_or_else(|| format_err!("No Duo sid found"))?
+            }
+        };
+
+        let devices = doc
+            .select("select[name='device'] option")
+            .map_err(|_| format_err!("No Duo devices found"))?
+            .filter_map(|option| {
+                let id = option.attributes.borrow().get("value")?.to_owned();
+
+                Some(DuoDevice {
+                    id,
+                    name: option.text_contents().trim().to_owned(),
+                })
+            })
+            .collect();
+
+        Ok(DuoFrame { sid, devices })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DuoPrompt {
+    txid: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DuoStatus {
+    status_code: String,
+    status: Option<String>,
+    result: Option<String>,
+    result_url: Option<String>,
+    cookie: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DuoResult {
+    cookie: String,
+}
+
+enum DuoFactor {
+    Push,
+    Call,
+    Passcode(String),
+}
+
+impl Client {
+    pub fn verify_duo(&self, factor: &Factor, state_token: String) -> Result<LoginResponse, Error> {
+        self.duo_handshake(
+            factor,
+            state_token,
+            DuoVerification::base_url,
+            choose_duo_factor,
+        )
+    }
+
+    /// Goes through the Duo prompt, gets Okta to accept its signed response and waits for it.
+    ///
+    /// Where Duo is and how the device and factor are picked are passed in, so that tests can use a
+    /// local Duo without HTTPS and skip the prompts.
+    fn duo_handshake<U, C>(
+        &self,
+        factor: &Factor,
+        state_token: String,
+        duo_url: U,
+        choose: C,
+    ) -> Result<LoginResponse, Error>
+    where
+        U: FnOnce(&DuoVerification) -> Result<Url, Error>,
+        C: FnOnce(&DuoFrame) -> Result<(usize, DuoFactor), Error>,
+    {
+        let factor_id = match *factor {
+            Factor::Web { ref id, .. } => id.clone(),
+            _ => bail!("{} is not a Duo factor", factor),
+        };
+
+        let request = FactorVerificationRequest::Web {
+            state_token: state_token.clone(),
+        };
+        let challenge_response = self.verify(&factor, &request)?;
+
+        trace!("Duo Challenge Response: {:?}", challenge_response);
+
+        let verification = challenge_response
+            .duo_verification()
+            .ok_or_else(|| format_err!("No Duo verification found in challenge response"))?;
+
+        let (tx_signature, app_signature) = verification.signatures()?;
+        let duo_url = duo_url(verification)?;
+
+        let frame = self.duo_frame(&duo_url, tx_signature)?;
+        debug!("Duo frame: {:?}", frame);
+
+        if frame.devices.is_empty() {
+            bail!("No Duo devices found");
+        }
+
+        let (device, duo_factor) = choose(&frame)?;
+        let device = frame
+            .devices
+            .get(device)
+            .ok_or_else(|| format_err!("No Duo device {} found", device))?;
+
+        let txid = self.duo_prompt(&duo_url, &frame.sid, device, &duo_factor)?;
+        let cookie = self.duo_wait(&duo_url, &frame.sid, &txid)?;
+
+        let sig_response = format!("{}:{}", cookie, app_signature);
+
+        self.post_form(
+            verification.callback_url()?,
+            &[
+                ("id", factor_id.as_str()),
+                ("stateToken", state_token.as_str()),
+                ("sig_response", sig_response.as_str()),
+            ],
+        )?;
+
+        self.wait_for_factor(challenge_response, &request, "Duo verification")
+    }
+
+    fn duo_frame(&self, duo_url: &Url, tx_signature: &str) -> Result<DuoFrame, Error> {
+        let parent = self.base_url().join("signin/verify/duo/web")?;
+
+        let mut auth_url = duo_url.join("frame/web/v1/auth")?;
+        auth_url
+            .query_pairs_mut()
+            .append_pair("tx", tx_signature)
+            .append_pair("parent", parent.as_str())
+            .append_pair("v", "2.6");
+
+        let mut response = self.post_form(
+            auth_url,
+            &[
+                ("parent", parent.as_str()),
+                ("java_version", ""),
+                ("flash_version", ""),
+            ],
+        )?;
+
+        let url = response.url().clone();
+        DuoFrame::parse(&url, &response.text()?)
+    }
+
+    fn duo_prompt(
+        &self,
+        duo_url: &Url,
+        sid: &str,
+        device: &DuoDevice,
+        factor: &DuoFactor,
+    ) -> Result<String, Error> {
+        let mut form = vec![
+            ("sid", sid),
+            ("device", device.id.as_str()),
+            ("out_of_date", "False"),
+        ];
+
+        match *factor {
+            DuoFactor::Push => form.push(("factor", "Duo Push")),
+            DuoFactor::Call => form.push(("factor", "Phone Call")),
+            DuoFactor::Passcode(ref passcode) => {
+                form.push(("factor", "Passcode"));
+                form.push(("passcode", passcode.as_str()));
+            }
+        }
+
+        let prompt: DuoResponse<DuoPrompt> = self
+            .post_form(duo_url.join("frame/prompt")?, &form)?
+            .json()?;
+
+        Ok(prompt.into_result()?.txid)
+    }
+
+    fn duo_wait(&self, duo_url: &Url, sid: &str, txid: &str) -> Result<String, Error> {
+        let started = Instant::now();
+
+        loop {
+            let status: DuoResponse<DuoStatus> = self
+                .post_form(
+                    duo_url.join("frame/status")?,
+                    &[("sid", sid), ("txid", txid)],
+                )?
+                .json()?;
+            let status = status.into_result()?;
+
+            trace!("Duo Status: {:?}", status);
+
+            match status.result.as_ref().map(|r| r.as_str()) {
+                Some("SUCCESS") => {
+                    eprintln!();
+
+                    if let Some(cookie) = status.cookie {
+                        return Ok(cookie);
+                    }
+
+                    let result_url = status
+                        .result_url
+                        .ok_or_else(|| format_err!("No Duo result found"))?;
+                    let result: DuoResponse<DuoResult> = self
+                        .post_form(duo_url.join(&result_url)?, &[("sid", sid)])?
+                        .json()?;
+
+                    return Ok(result.into_result()?.cookie);
+                }
+                Some("FAILURE") => bail!(
+                    "Duo verification failed ({})",
+                    status.status.unwrap_or(status.status_code)
+                ),
+                _ => {}
+            }
+
+            if started.elapsed() >= self.mfa_timeout() {
+                bail!(
+                    "Duo verification was not completed within {} seconds",
+                    self.mfa_timeout().as_secs()
+                );
+            }
+
+            eprint!(".");
+            io::stderr().flush()?;
+            sleep(DUO_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Asks which device to use, when there is more than one, and how to verify with it
+fn choose_duo_factor(frame: &DuoFrame) -> Result<(usize, DuoFactor), Error> {
+    let device = if frame.devices.len() == 1 {
+        0
+    } else {
+        let mut menu = Select::new();
+        for device in &frame.devices {
+            menu.item(&device.name);
+        }
+
+        menu.interact()?
+    };
+
+    let mut menu = Select::new();
+    menu.item("Duo Push");
+    menu.item("Phone Call");
+    menu.item("Passcode");
+
+    let duo_factor = match menu.interact()? {
+        0 => DuoFactor::Push,
+        1 => DuoFactor::Call,
+        _ => DuoFactor::Passcode(Input::new("Duo passcode").interact()?),
+    };
+
+    Ok((device, duo_factor))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+    use okta::Organization;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    /// Serves what `respond` returns for each request path, and passes on the paths and bodies
+    fn mock_server<F>(mut respond: F) -> (Url, Receiver<(String, String)>)
+    where
+        F: FnMut(&str, &Url) -> String + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        let url = base_url.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let path = request_line
+                    .split_whitespace()
+                    .nth(1)
+                    .unwrap()
+                    .split('?')
+                    .next()
+                    .unwrap()
+                    .to_owned();
+
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+
+                    let mut parts = header.splitn(2, ':');
+                    if parts.next().unwrap().eq_ignore_ascii_case("content-length") {
+                        content_length = parts.next().unwrap().trim().parse().unwrap();
+                    }
+                }
+
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let response = respond(&path, &url);
+                sender
+                    .send((path, String::from_utf8(body).unwrap()))
+                    .unwrap();
+
+                let content_type = if response.starts_with('<') {
+                    "text/html"
+                } else {
+                    "application/json"
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    content_type,
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+
+        (base_url, receiver)
+    }
+
+    fn okta_response(url: &Url, status: &str) -> String {
+        format!(
+            r#"{{
+                "stateToken": "STATE_TOKEN",
+                "expiresAt": "2015-11-03T10:15:57.000Z",
+                "status": "{status}",
+                "factorResult": "WAITING",
+                "_embedded": {{
+                    "factor": {{
+                        "id": "FACTOR_ID",
+                        "factorType": "web",
+                        "provider": "DUO",
+                        "profile": {{ "credentialId": "user@example.com" }},
+                        "_embedded": {{
+                            "verification": {{
+                                "host": "api-1234.duosecurity.com",
+                                "signature": "TX|TX_SIGNATURE:APP|APP_SIGNATURE",
+                                "_links": {{
+                                    "complete": {{
+                                        "href": "{url}api/v1/authn/factors/FACTOR_ID/lifecycle/duoCallback",
+                                        "hints": {{ "allow": ["POST"] }}
+                                    }}
+                                }}
+                            }}
+                        }}
+                    }}
+                }},
+                "_links": {{
+                    "next": {{
+                        "name": "poll",
+                        "href": "{url}api/v1/authn/factors/FACTOR_ID/verify",
+                        "hints": {{ "allow": ["POST"] }}
+                    }}
+                }}
+            }}"#,
+            status = status,
+            url = url
+        )
+    }
+
+    #[test]
+    fn duo_handshake() {
+        let mut verifications = 0;
+        let (url, requests) = mock_server(move |path, url| match path {
+            "/api/v1/authn/factors/FACTOR_ID/verify" => {
+                verifications += 1;
+                match verifications {
+                    1 | 2 => okta_response(url, "MFA_CHALLENGE"),
+                    _ => String::from(
+                        r#"{
+                            "expiresAt": "2015-11-03T10:15:57.000Z",
+                            "status": "SUCCESS",
+                            "sessionToken": "SESSION_TOKEN"
+                        }"#,
+                    ),
+                }
+            }
+            "/frame/web/v1/auth" => String::from(
+                r#"<form action="/frame/prompt" method="post">
+                    <input type="hidden" name="sid" value="SID">
+                    <select name="device">
+                        <option value="phone1">iOS (XXX-XXX-1234)</option>
+                        <option value="phone2">Landline (XXX-XXX-5678)</option>
+                    </select>
+                </form>"#,
+            ),
+            "/frame/prompt" => String::from(r#"{ "stat": "OK", "response": { "txid": "TXID" } }"#),
+            "/frame/status" => String::from(
+                r#"{
+                    "stat": "OK",
+                    "response": { "status_code": "allow", "result": "SUCCESS", "cookie": "AUTH|COOKIE" }
+                }"#,
+            ),
+            "/api/v1/authn/factors/FACTOR_ID/lifecycle/duoCallback" => String::from("{}"),
+            _ => panic!("Unexpected request to {}", path),
+        });
+
+        let factor: Factor = serde_json::from_str(&format!(
+            r#"{{
+                "id": "FACTOR_ID",
+                "factorType": "web",
+                "provider": "DUO",
+                "profile": {{ "credentialId": "user@example.com" }},
+                "_links": {{
+                    "verify": {{
+                        "href": "{}api/v1/authn/factors/FACTOR_ID/verify",
+                        "hints": {{ "allow": ["POST"] }}
+                    }}
+                }}
+            }}"#,
+            url
+        ))
+        .unwrap();
+
+        let client = Client::new(Organization {
+            name: String::from("example"),
+            base_url: url.clone(),
+        });
+        let response = client
+            .duo_handshake(
+                &factor,
+                String::from("STATE_TOKEN"),
+                |_| Ok(url.clone()),
+                |frame| {
+                    assert_eq!(frame.sid, "SID");
+                    Ok((1, DuoFactor::Push))
+                },
+            )
+            .unwrap();
+
+        assert_eq!(response.session_token, Some(String::from("SESSION_TOKEN")));
+
+        let requests = requests.try_iter().collect::<Vec<(String, String)>>();
+        assert_eq!(
+            requests
+                .iter()
+                .map(|&(ref path, _)| path.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "/api/v1/authn/factors/FACTOR_ID/verify",
+                "/frame/web/v1/auth",
+                "/frame/prompt",
+                "/frame/status",
+                "/api/v1/authn/factors/FACTOR_ID/lifecycle/duoCallback",
+                "/api/v1/authn/factors/FACTOR_ID/verify",
+                "/api/v1/authn/factors/FACTOR_ID/verify",
+            ]
+        );
+        assert!(requests[2].1.contains("device=phone2"));
+        assert!(requests[2].1.contains("factor=Duo+Push"));
+        assert!(requests[4]
+            .1
+            .contains("sig_response=AUTH%7CCOOKIE%3AAPP%7CAPP_SIGNATURE"));
+    }
+
+    #[test]
+    fn parse_duo_challenge() {
+        let response: LoginResponse = serde_json::from_str(
+            r#"{
+                "stateToken": "STATE_TOKEN",
+                "expiresAt": "2015-11-03T10:15:57.000Z",
+                "status": "MFA_CHALLENGE",
+                "factorResult": "WAITING",
+                "_embedded": {
+                    "factor": {
+                        "id": "FACTOR_ID",
+                        "factorType": "web",
+                        "provider": "DUO",
+                        "profile": { "credentialId": "user@example.com" },
+                        "_embedded": {
+                            "verification": {
+                                "host": "api-1234.duosecurity.com",
+                                "signature": "TX|TX_SIGNATURE:APP|APP_SIGNATURE",
+                                "_links": {
+                                    "complete": {
+                                        "href": "https://example.okta.com/api/v1/authn/factors/FACTOR_ID/lifecycle/duoCallback",
+                                        "hints": { "allow": ["POST"] }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let verification = response.duo_verification().unwrap();
+
+        assert_eq!(
+            verification.signatures().unwrap(),
+            ("TX|TX_SIGNATURE", "APP|APP_SIGNATURE")
+        );
+        assert_eq!(
+            verification.base_url().unwrap().as_str(),
+            "https://api-1234.duosecurity.com/"
+        );
+        assert_eq!(
+            verification.callback_url().unwrap().as_str(),
+            "https://example.okta.com/api/v1/authn/factors/FACTOR_ID/lifecycle/duoCallback"
+        );
+    }
+
+    #[test]
+    fn parse_duo_frame() {
+        let url = Url::parse("https://api-1234.duosecurity.com/frame/prompt?sid=SID").unwrap();
+        let frame = DuoFrame::parse(
+            &url,
+            r#"<form action="/frame/prompt" method="post">
+                <select name="device">
+                    <option value="phone1">iOS (XXX-XXX-1234)</option>
+                    <option value="phone2">Landline (XXX-XXX-5678)</option>
+                </select>
+            </form>"#,
+        )
+        .unwrap();
+
+        assert_eq!(frame.sid, "SID");
+        assert_eq!(
+            frame.devices,
+            vec![
+                DuoDevice {
+                    id: String::from("phone1"),
+                    name: String::from("iOS (XXX-XXX-1234)"),
+                },
+                DuoDevice {
+                    id: String::from("phone2"),
+                    name: String::from("Landline (XXX-XXX-5678)"),
+                },
+            ]
+        );
+    }
+}
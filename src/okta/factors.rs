@@ -1,11 +1,25 @@
+use dialoguer::Input;
 use failure::Error;
-use okta::auth::LoginResponse;
+use itertools::Itertools;
+use okta::auth::{FactorResult, LoginResponse, LoginState};
 use okta::client::Client;
+use okta::duo::DuoVerification;
+use okta::prompt_hidden;
 use okta::Links;
 use okta::Links::Multi;
 use okta::Links::Single;
+use reqwest;
+use reqwest::StatusCode;
+use reqwest::Url;
 use std::collections::HashMap;
 use std::fmt;
+use std::io::{self, Write};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// What to enter instead of a code to have it sent again
+const RESEND: &str = "resend";
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "lowercase", tag = "factorType")]
@@ -80,8 +94,10 @@ pub enum Factor {
         provider: FactorProvider,
         status: Option<FactorStatus>,
         profile: WebFactorProfile,
-        #[serde(rename = "_links")]
+        #[serde(rename = "_links", default)]
         links: HashMap<String, Links>,
+        #[serde(rename = "_embedded")]
+        embedded: Option<WebFactorEmbedded>,
     },
 }
 
@@ -147,11 +163,51 @@ pub struct WebFactorProfile {
     credential_id: String,
 }
 
+/// A factor the user can, or has to, enroll in
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FactorEnrollment {
+    factor_type: String,
+    provider: String,
+    enrollment: Option<String>,
+}
+
+impl fmt::Display for FactorEnrollment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.provider, self.factor_type)?;
+        if let Some(ref enrollment) = self.enrollment {
+            write!(f, " ({})", enrollment.to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct FactorEnrollments(pub Vec<FactorEnrollment>);
+
+impl fmt::Display for FactorEnrollments {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            write!(f, "no factors offered")
+        } else {
+            write!(f, "{}", self.0.iter().join(", "))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebFactorEmbedded {
+    pub verification: Option<DuoVerification>,
+}
+
 #[derive(Deserialize, Debug, Serialize)]
 #[serde(untagged)]
 pub enum FactorVerificationRequest {
     #[serde(rename_all = "camelCase")]
-    Question { answer: String },
+    Push { state_token: String },
+    #[serde(rename_all = "camelCase")]
+    Question { state_token: String, answer: String },
     #[serde(rename_all = "camelCase")]
     Sms {
         state_token: String,
@@ -159,11 +215,144 @@ pub enum FactorVerificationRequest {
         pass_code: Option<String>,
     },
     #[serde(rename_all = "camelCase")]
-    Call { pass_code: Option<String> },
+    Call {
+        state_token: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pass_code: Option<String>,
+    },
     #[serde(rename_all = "camelCase")]
-    Totp { pass_code: String },
+    Totp {
+        state_token: String,
+        pass_code: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Token {
+        state_token: String,
+        pass_code: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        next_pass_code: Option<String>,
+    },
     #[serde(rename_all = "camelCase")]
-    Token { pass_code: String },
+    Web { state_token: String },
+}
+
+/// A factor preference from the organization file, either just a type (`factor = "push"`)
+/// or a table narrowing it down further (`factor = { type = "sms", phone = "1234" }`)
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FactorSelector {
+    Type(String),
+    Detailed {
+        #[serde(rename = "type")]
+        factor_type: String,
+        provider: Option<String>,
+        phone: Option<String>,
+    },
+}
+
+impl FactorSelector {
+    fn factor_type(&self) -> &str {
+        match *self {
+            FactorSelector::Type(ref factor_type) => factor_type,
+            FactorSelector::Detailed {
+                ref factor_type, ..
+            } => factor_type,
+        }
+    }
+}
+
+impl Factor {
+    fn links(&self) -> &HashMap<String, Links> {
+        match *self {
+            Factor::Push { ref links, .. }
+            | Factor::Sms { ref links, .. }
+            | Factor::Call { ref links, .. }
+            | Factor::Token { ref links, .. }
+            | Factor::Totp { ref links, .. }
+            | Factor::Hotp { ref links, .. }
+            | Factor::Question { ref links, .. }
+            | Factor::Web { ref links, .. } => links,
+        }
+    }
+
+    pub fn matches(&self, selector: &FactorSelector) -> bool {
+        let (factor_type, provider, phone_number) = match *self {
+            Factor::Push { ref provider, .. } => ("push", provider, None),
+            Factor::Sms {
+                ref provider,
+                ref profile,
+                ..
+            } => ("sms", provider, Some(&profile.phone_number)),
+            Factor::Call {
+                ref provider,
+                ref profile,
+                ..
+            } => ("call", provider, Some(&profile.phone_number)),
+            Factor::Token { ref provider, .. } => ("token", provider, None),
+            Factor::Totp { ref provider, .. } => ("totp", provider, None),
+            Factor::Hotp { ref provider, .. } => ("hotp", provider, None),
+            Factor::Question { ref provider, .. } => ("question", provider, None),
+            Factor::Web { ref provider, .. } => ("web", provider, None),
+        };
+
+        if !selector.factor_type().eq_ignore_ascii_case(factor_type) {
+            return false;
+        }
+
+        match *selector {
+            FactorSelector::Type(_) => true,
+            FactorSelector::Detailed {
+                provider: ref wanted_provider,
+                phone: ref wanted_phone,
+                ..
+            } => {
+                let provider_matches = wanted_provider
+                    .as_ref()
+                    .map(|p| p.eq_ignore_ascii_case(provider.name()))
+                    .unwrap_or(true);
+
+                // Okta masks most of the phone number, so match on the trailing digits only
+                let phone_matches = match (wanted_phone, phone_number) {
+                    (&Some(ref wanted), Some(actual)) => digits(actual).ends_with(&digits(wanted)),
+                    (&Some(_), None) => false,
+                    (&None, _) => true,
+                };
+
+                provider_matches && phone_matches
+            }
+        }
+    }
+}
+
+fn digits(s: &str) -> String {
+    s.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+impl FactorProvider {
+    /// The name Okta uses for the provider, and which the `provider` of a factor selector is matched against
+    fn name(&self) -> &'static str {
+        match *self {
+            FactorProvider::Okta => "OKTA",
+            FactorProvider::Rsa => "RSA",
+            FactorProvider::Symantec => "SYMANTEC",
+            FactorProvider::Google => "GOOGLE",
+            FactorProvider::Duo => "DUO",
+            FactorProvider::Yubico => "YUBICO",
+        }
+    }
+}
+
+impl fmt::Display for FactorProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FactorProvider::Okta => write!(f, "Okta"),
+            FactorProvider::Rsa => write!(f, "RSA SecurID"),
+            FactorProvider::Symantec => write!(f, "Symantec VIP"),
+            FactorProvider::Google => write!(f, "Google"),
+            FactorProvider::Duo => write!(f, "Duo"),
+            FactorProvider::Yubico => write!(f, "YubiKey"),
+        }
+    }
 }
 
 impl fmt::Display for Factor {
@@ -172,11 +361,17 @@ impl fmt::Display for Factor {
             Factor::Push { .. } => write!(f, "Okta Verify Push"),
             Factor::Sms { ref profile, .. } => write!(f, "Okta SMS to {}", profile.phone_number),
             Factor::Call { ref profile, .. } => write!(f, "Okta Call to {}", profile.phone_number),
-            Factor::Token { .. } => write!(f, "Okta One-time Password"),
-            Factor::Totp { .. } => write!(f, "Okta Time-based One-time Password"),
-            Factor::Hotp { .. } => write!(f, "Okta Hardware One-time Password"),
-            Factor::Question { ref profile, .. } => write!(f, "Question: {}", profile.question),
-            Factor::Web { .. } => write!(f, "Okta Web"),
+            Factor::Token { ref provider, .. } => write!(f, "{} One-time Password", provider),
+            Factor::Totp { ref provider, .. } => {
+                write!(f, "{} Time-based One-time Password", provider)
+            }
+            Factor::Hotp { ref provider, .. } => {
+                write!(f, "{} Hardware One-time Password", provider)
+            }
+            Factor::Question { ref profile, .. } => {
+                write!(f, "Question: {}", profile.question_text)
+            }
+            Factor::Web { ref provider, .. } => write!(f, "{} Web", provider),
         }
     }
 }
@@ -187,19 +382,320 @@ impl Client {
         factor: &Factor,
         request: &FactorVerificationRequest,
     ) -> Result<LoginResponse, Error> {
-        match *factor {
-            Factor::Sms { ref links, .. } => {
-                let url = match links.get("verify").unwrap() {
-                    Single(ref link) => link.href.clone(),
-                    Multi(ref links) => links.first().unwrap().href.clone(),
-                };
+        self.post_absolute(link_url(factor.links(), "verify")?, request)
+    }
+
+    pub fn verify_sms(&self, factor: &Factor, state_token: String) -> Result<LoginResponse, Error> {
+        let challenge_response = self.verify(
+            &factor,
+            &FactorVerificationRequest::Sms {
+                state_token,
+                pass_code: None,
+            },
+        )?;
 
-                self.post_absolute(url, request)
+        self.verify_sent_code(factor, challenge_response, |state_token, pass_code| {
+            FactorVerificationRequest::Sms {
+                state_token,
+                pass_code,
             }
-            _ => {
-                // TODO
-                bail!("Unsupported MFA method")
+        })
+    }
+
+    pub fn verify_call(
+        &self,
+        factor: &Factor,
+        state_token: String,
+    ) -> Result<LoginResponse, Error> {
+        let challenge_response = self.verify(
+            &factor,
+            &FactorVerificationRequest::Call {
+                state_token,
+                pass_code: None,
+            },
+        )?;
+
+        self.verify_sent_code(factor, challenge_response, |state_token, pass_code| {
+            FactorVerificationRequest::Call {
+                state_token,
+                pass_code,
             }
+        })
+    }
+
+    /// Asks for the code sent by SMS or by a call until it is accepted, sending it again on request
+    fn verify_sent_code<F>(
+        &self,
+        factor: &Factor,
+        mut challenge_response: LoginResponse,
+        request: F,
+    ) -> Result<LoginResponse, Error>
+    where
+        F: Fn(String, Option<String>) -> FactorVerificationRequest,
+    {
+        loop {
+            trace!("Factor Challenge Response: {:?}", challenge_response);
+
+            let state_token = challenge_response
+                .state_token
+                .clone()
+                .ok_or_else(|| format_err!("No state token found in factor challenge response"))?;
+
+            let pass_code = Input::new(&format!("Code from {} (or '{}')", factor, RESEND))
+                .interact()?
+                .trim()
+                .to_owned();
+
+            if pass_code.eq_ignore_ascii_case(RESEND) {
+                match challenge_response.resend_url() {
+                    Some(resend_url) => {
+                        challenge_response =
+                            self.post_absolute(resend_url, &request(state_token, None))?;
+                    }
+                    None => warn!("{} cannot be sent again", factor),
+                }
+                continue;
+            }
+
+            match self.verify(&factor, &request(state_token, Some(pass_code))) {
+                Err(ref e) if is_rejected(e) => warn!(
+                    "Invalid code, please try again, or enter '{}' to get another one",
+                    RESEND
+                ),
+                result => return result,
+            }
+        }
+    }
+
+    pub fn verify_totp(
+        &self,
+        factor: &Factor,
+        state_token: String,
+    ) -> Result<LoginResponse, Error> {
+        loop {
+            let pass_code = Input::new(&format!("{} code", factor)).interact()?;
+
+            let request = FactorVerificationRequest::Totp {
+                state_token: state_token.clone(),
+                pass_code,
+            };
+
+            match self.verify(&factor, &request) {
+                Err(ref e) if is_rejected(e) => warn!("Invalid passcode, please try again"),
+                result => return result,
+            }
+        }
+    }
+
+    pub fn verify_token(
+        &self,
+        factor: &Factor,
+        state_token: String,
+    ) -> Result<LoginResponse, Error> {
+        loop {
+            let pass_code = Input::new(&format!("{} passcode", factor)).interact()?;
+
+            let request = FactorVerificationRequest::Token {
+                state_token: state_token.clone(),
+                pass_code: pass_code.clone(),
+                next_pass_code: None,
+            };
+
+            let response = match self.verify(&factor, &request) {
+                Err(ref e) if is_rejected(e) => {
+                    warn!("Invalid passcode, please try again");
+                    continue;
+                }
+                result => result?,
+            };
+
+            trace!("Token Response: {:?}", response);
+
+            // RSA SecurID may ask for the next code from the token to resynchronize it
+            if response.factor_result != Some(FactorResult::Challenge) {
+                return Ok(response);
+            }
+
+            info!("The next passcode is required, wait for the token to change");
+
+            let next_pass_code = Input::new("Next passcode").interact()?;
+
+            let request = FactorVerificationRequest::Token {
+                state_token: response.state_token.clone().unwrap_or(state_token),
+                pass_code,
+                next_pass_code: Some(next_pass_code),
+            };
+
+            return self.verify(&factor, &request);
+        }
+    }
+
+    pub fn verify_question(
+        &self,
+        factor: &Factor,
+        state_token: String,
+    ) -> Result<LoginResponse, Error> {
+        let question_text = match *factor {
+            Factor::Question { ref profile, .. } => &profile.question_text,
+            _ => bail!("{} is not a security question factor", factor),
+        };
+
+        let answer = prompt_hidden(question_text)?;
+
+        let request = FactorVerificationRequest::Question {
+            state_token,
+            answer,
+        };
+
+        match self.verify(&factor, &request) {
+            Err(ref e) if is_rejected(e) => bail!("Incorrect answer to security question"),
+            result => result,
+        }
+    }
+
+    pub fn verify_push(
+        &self,
+        factor: &Factor,
+        state_token: String,
+    ) -> Result<LoginResponse, Error> {
+        let request = FactorVerificationRequest::Push { state_token };
+
+        let response = self.verify(&factor, &request)?;
+
+        info!("Push notification sent, waiting for approval");
+
+        self.wait_for_factor(response, &request, "Push notification")
+    }
+
+    /// Polls Okta until a factor verified elsewhere is accepted, for at most the MFA timeout
+    pub fn wait_for_factor(
+        &self,
+        mut response: LoginResponse,
+        request: &FactorVerificationRequest,
+        description: &str,
+    ) -> Result<LoginResponse, Error> {
+        let started = Instant::now();
+
+        loop {
+            trace!("{} Response: {:?}", description, response);
+
+            if response.status == LoginState::Success {
+                eprintln!();
+                return Ok(response);
+            }
+
+            match response.factor_result {
+                Some(FactorResult::Waiting) | None => {}
+                Some(FactorResult::Rejected) => bail!("{} was rejected", description),
+                Some(FactorResult::Timeout) => bail!("{} timed out", description),
+                Some(ref result) => bail!("{} failed ({:?})", description, result),
+            }
+
+            if started.elapsed() >= self.mfa_timeout() {
+                bail!(
+                    "{} was not approved within {} seconds",
+                    description,
+                    self.mfa_timeout().as_secs()
+                );
+            }
+
+            eprint!(".");
+            io::stderr().flush()?;
+            sleep(POLL_INTERVAL);
+
+            let poll_url = response
+                .poll_url()
+                .ok_or_else(|| format_err!("No poll link found for {}", description))?;
+
+            response = self.post_absolute(poll_url, request)?;
         }
     }
 }
+
+fn link_url(links: &HashMap<String, Links>, name: &str) -> Result<Url, Error> {
+    match links.get(name) {
+        Some(Single(ref link)) => Ok(link.href.clone()),
+        Some(Multi(ref links)) => links
+            .first()
+            .map(|link| link.href.clone())
+            .ok_or_else(|| format_err!("No {} link found", name)),
+        None => bail!("No {} link found", name),
+    }
+}
+
+// Okta responds with 403 Forbidden when a passcode or answer is incorrect
+fn is_rejected(e: &Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        .map(|status| status == StatusCode::FORBIDDEN)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+    use toml;
+
+    fn sms_factor() -> Factor {
+        serde_json::from_str(
+            r#"{
+                "id": "FACTOR_ID",
+                "factorType": "sms",
+                "provider": "OKTA",
+                "profile": { "phoneNumber": "+1 XXX-XXX-1234" },
+                "_links": {}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn select_by_type() {
+        let selector: FactorSelector = toml::Value::String(String::from("SMS")).try_into().unwrap();
+
+        assert!(sms_factor().matches(&selector));
+        assert!(!sms_factor().matches(&FactorSelector::Type(String::from("push"))));
+    }
+
+    #[test]
+    fn select_by_phone() {
+        let selector: FactorSelector =
+            toml::from_str::<toml::Value>(r#"factor = { type = "sms", phone = "...1234" }"#)
+                .unwrap()["factor"]
+                .clone()
+                .try_into()
+                .unwrap();
+
+        assert_eq!(
+            selector,
+            FactorSelector::Detailed {
+                factor_type: String::from("sms"),
+                provider: None,
+                phone: Some(String::from("...1234")),
+            }
+        );
+        assert!(sms_factor().matches(&selector));
+
+        let other_phone = FactorSelector::Detailed {
+            factor_type: String::from("sms"),
+            provider: None,
+            phone: Some(String::from("5678")),
+        };
+        assert!(!sms_factor().matches(&other_phone));
+    }
+
+    #[test]
+    fn select_by_provider() {
+        let selector = |provider: &str| FactorSelector::Detailed {
+            factor_type: String::from("sms"),
+            provider: Some(String::from(provider)),
+            phone: None,
+        };
+
+        assert!(sms_factor().matches(&selector("okta")));
+        assert!(sms_factor().matches(&selector("OKTA")));
+        assert!(!sms_factor().matches(&selector("google")));
+    }
+}
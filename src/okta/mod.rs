@@ -1,9 +1,12 @@
 pub mod auth;
 pub mod client;
+pub mod duo;
 pub mod factors;
 pub mod sessions;
 pub mod users;
 
+#[cfg(not(windows))]
+use dialoguer::PasswordInput;
 use failure::{Compat, Error};
 use kuchiki;
 use kuchiki::traits::TendrilSink;
@@ -11,6 +14,8 @@ use okta::auth::LoginRequest;
 use okta::client::Client;
 use regex::Regex;
 use reqwest::Url;
+#[cfg(windows)]
+use rpassword;
 use serde_str;
 
 use saml::Response as SamlResponse;
@@ -78,6 +83,17 @@ impl Client {
     }
 }
 
+// We use rpassword here because dialoguer hangs on windows
+#[cfg(windows)]
+fn prompt_hidden(prompt: &str) -> Result<String, Error> {
+    rpassword::prompt_password_stderr(&format!("{}: ", prompt)).map_err(|e| e.into())
+}
+
+#[cfg(not(windows))]
+fn prompt_hidden(prompt: &str) -> Result<String, Error> {
+    PasswordInput::new(prompt).interact().map_err(|e| e.into())
+}
+
 fn extract_state_token(text: &str) -> Result<String, Error> {
     let re = Regex::new(r#"var stateToken = '(.+)';"#)?;
 
@@ -102,13 +118,21 @@ pub fn extract_saml_response(text: String) -> Result<SamlResponse, ExtractSamlRe
         .ok_or(ExtractSamlResponseError::NotFound)?;
 
     trace!("SAML: {}", saml);
-    saml.parse().map_err(|e: Error| e.into())
+    let saml: SamlResponse = saml.parse()?;
+
+    if saml.roles.is_empty() {
+        return Err(ExtractSamlResponseError::NoRoles);
+    }
+
+    Ok(saml)
 }
 
 #[derive(Fail, Debug)]
 pub enum ExtractSamlResponseError {
     #[fail(display = "No SAML found")]
     NotFound,
+    #[fail(display = "No AWS roles found in SAML response")]
+    NoRoles,
     #[fail(display = "{}", _0)]
     Invalid(#[cause] Compat<Error>),
 }
@@ -1,10 +1,13 @@
+use chrono::{DateTime, Utc};
 use failure::Error;
 use itertools::Itertools;
 
 use okta::client::Client;
 
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -16,6 +19,58 @@ pub struct SessionRequest {
 #[serde(rename_all = "camelCase")]
 pub struct SessionResponse {
     pub id: String,
+    expires_at: Option<String>,
+}
+
+impl SessionResponse {
+    pub fn expiry(&self) -> Option<DateTime<Utc>> {
+        self.expires_at
+            .as_ref()
+            .and_then(|e| DateTime::parse_from_rfc3339(e).ok())
+            .map(|e| e.with_timezone(&Utc))
+    }
+}
+
+/// An Okta session id kept between runs, so that logging in (and MFA) can be skipped
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedSession {
+    pub id: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl CachedSession {
+    pub fn from_response(response: &SessionResponse) -> Option<CachedSession> {
+        response.expiry().map(|expires_at| CachedSession {
+            id: response.id.clone(),
+            expires_at,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
+
+impl FromStr for CachedSession {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut splitted = s.split_whitespace();
+
+        match (splitted.next(), splitted.next(), splitted.next()) {
+            (Some(id), Some(expires_at), None) => Ok(CachedSession {
+                id: String::from(id),
+                expires_at: DateTime::parse_from_rfc3339(expires_at)?.with_timezone(&Utc),
+            }),
+            _ => bail!("Invalid cached session {}", s),
+        }
+    }
+}
+
+impl fmt::Display for CachedSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.id, self.expires_at.to_rfc3339())
+    }
 }
 
 #[allow(dead_code)]
@@ -50,6 +105,18 @@ impl Client {
             },
         )
     }
+
+    /// Fetches the current session, which fails if it is no longer valid
+    pub fn get_session(&self) -> Result<SessionResponse, Error> {
+        self.get("api/v1/sessions/me")
+    }
+
+    pub fn refresh_session(&self) -> Result<SessionResponse, Error> {
+        self.post(
+            "api/v1/sessions/me/lifecycle/refresh",
+            &HashMap::<String, String>::new(),
+        )
+    }
 }
 
 #[cfg(test)]
@@ -107,4 +174,14 @@ mod tests {
             "Not enough elements in arn:aws:iam::123456789012:saml-provider/okta-idp"
         );
     }
+
+    #[test]
+    fn cached_session_round_trip() {
+        let cached: CachedSession = "SESSION_ID 2015-11-03T10:15:57Z".parse().unwrap();
+
+        assert_eq!(cached.id, "SESSION_ID");
+        assert_eq!(cached.to_string(), "SESSION_ID 2015-11-03T10:15:57+00:00");
+        assert_eq!(cached.to_string().parse::<CachedSession>().unwrap(), cached);
+        assert!(cached.is_expired());
+    }
 }
@@ -11,6 +11,8 @@ use aws::role::Role;
 #[derive(Debug)]
 pub struct Response {
     pub raw: String,
+    /// The sign-in URL the response is meant to be posted to
+    pub destination: Option<String>,
     pub roles: HashSet<Role>,
 }
 
@@ -31,6 +33,18 @@ impl FromStr for Response {
 
         let mut context = Context::new();
         context.set_namespace("saml2", "urn:oasis:names:tc:SAML:2.0:assertion");
+        context.set_namespace("saml2p", "urn:oasis:names:tc:SAML:2.0:protocol");
+
+        let destination_xpath = Factory::new()
+            .build("string(/saml2p:Response/@Destination)")?
+            .ok_or_else(|| format_err!("No XPath was compiled"))?;
+
+        let destination = match destination_xpath.evaluate(&context, document.root())? {
+            Value::String(ref destination) if !destination.is_empty() => {
+                Some(destination.to_owned())
+            }
+            _ => None,
+        };
 
         let roles = match xpath.evaluate(&context, document.root())? {
             Value::Nodeset(ns) => ns
@@ -42,6 +56,7 @@ impl FromStr for Response {
 
         Ok(Response {
             raw: s.to_owned(),
+            destination,
             roles,
         })
     }
@@ -80,6 +95,10 @@ mod tests {
         .collect::<HashSet<Role>>();
 
         assert_eq!(response.roles, expected_roles);
+        assert_eq!(
+            response.destination,
+            Some(String::from("http://sp.example.com/demo1/index.php?acs"))
+        );
     }
 
     #[test]
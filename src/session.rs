@@ -0,0 +1,428 @@
+use dialoguer::{Confirmation, Select};
+use failure::{Error, ResultExt};
+use rusoto_core::Region;
+use rusoto_sts::Credentials;
+use std::collections::HashSet;
+use std::env;
+use std::time::Duration;
+
+use aws;
+use aws::config::ConfigStore;
+use aws::partition::Partition;
+use aws::role::{Role, RoleCredentials};
+use config::organization::{Organization, Profile};
+use error::ErrorKind;
+use okta::auth::LoginRequest;
+use okta::client::Client as OktaClient;
+use okta::sessions::CachedSession;
+use okta::Organization as OktaOrganization;
+
+/// Supplies the Okta password of a user, and keeps it once it is known to be correct.
+///
+/// Providers can also keep the Okta session between runs, so that logging in is skipped.
+pub trait PasswordProvider {
+    fn get_password(
+        &self,
+        organization: &OktaOrganization,
+        username: &str,
+    ) -> Result<String, Error>;
+
+    fn save_password(
+        &self,
+        _organization: &OktaOrganization,
+        _username: &str,
+        _password: &str,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_session(
+        &self,
+        _organization: &OktaOrganization,
+        _username: &str,
+    ) -> Option<CachedSession> {
+        None
+    }
+
+    fn save_session(
+        &self,
+        _organization: &OktaOrganization,
+        _username: &str,
+        _session: &CachedSession,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A password known ahead of time, which is never saved anywhere
+pub struct StaticPasswordProvider(pub String);
+
+impl PasswordProvider for StaticPasswordProvider {
+    fn get_password(&self, _: &OktaOrganization, _: &str) -> Result<String, Error> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Logs into an Okta organization
+pub struct Builder {
+    organization: Organization,
+    password_provider: Option<Box<dyn PasswordProvider>>,
+    mfa_timeout: Duration,
+    interactive: bool,
+    aws_config: Option<ConfigStore>,
+}
+
+impl Builder {
+    pub fn new(organization: Organization) -> Builder {
+        Builder {
+            organization,
+            password_provider: None,
+            mfa_timeout: Duration::from_secs(60),
+            interactive: true,
+            aws_config: None,
+        }
+    }
+
+    pub fn password_provider<P: PasswordProvider + 'static>(mut self, provider: P) -> Builder {
+        self.password_provider = Some(Box::new(provider));
+        self
+    }
+
+    /// How long to wait for push notifications and other out-of-band factors
+    pub fn mfa_timeout(mut self, mfa_timeout: Duration) -> Builder {
+        self.mfa_timeout = mfa_timeout;
+        self
+    }
+
+    /// Whether roles can be picked from a menu when a profile has none, or one that is missing
+    pub fn interactive(mut self, interactive: bool) -> Builder {
+        self.interactive = interactive;
+        self
+    }
+
+    /// The `~/.aws/config` to read profile regions from, when it has already been read
+    pub fn aws_config(mut self, aws_config: ConfigStore) -> Builder {
+        self.aws_config = Some(aws_config);
+        self
+    }
+
+    pub fn login(self) -> Result<Session, Error> {
+        let password_provider = self
+            .password_provider
+            .ok_or_else(|| format_err!("No password provider given"))?;
+
+        let okta_organization = &self.organization.okta_organization;
+        let username = &self.organization.username;
+
+        let mut client = OktaClient::new(okta_organization.clone());
+        client.set_mfa_timeout(self.mfa_timeout);
+        client.set_factor_selector(self.organization.factor.clone());
+
+        let cached_session = password_provider
+            .get_session(okta_organization, username)
+            .filter(|session| !session.is_expired());
+
+        let resumed_session = match cached_session {
+            Some(cached_session) => {
+                client.set_session_id(cached_session.id);
+                client
+                    .get_session()
+                    .and_then(|_| client.refresh_session())
+                    .map_err(|e| debug!("Cached Okta session was rejected ({})", e))
+                    .ok()
+            }
+            None => None,
+        };
+
+        let session = match resumed_session {
+            Some(session) => {
+                info!("Reusing Okta session");
+                session
+            }
+            None => {
+                let password = password_provider.get_password(okta_organization, username)?;
+
+                let authentication = client.authenticate(&LoginRequest::from_credentials(
+                    username.clone(),
+                    password.clone(),
+                ))?;
+
+                let password = authentication.new_password.unwrap_or(password);
+
+                let session = client.new_session(authentication.session_token, &HashSet::new())?;
+                client.set_session_id(session.id.clone());
+
+                password_provider.save_password(okta_organization, username, &password)?;
+
+                session
+            }
+        };
+
+        if let Some(cached_session) = CachedSession::from_response(&session) {
+            password_provider.save_session(okta_organization, username, &cached_session)?;
+        }
+
+        let aws_config = self.aws_config.or_else(|| {
+            ConfigStore::new()
+                .map_err(|e| debug!("Could not read the AWS config ({})", e))
+                .ok()
+        });
+
+        Ok(Session {
+            organization: self.organization,
+            client,
+            interactive: self.interactive,
+            aws_config,
+        })
+    }
+}
+
+/// An AWS application the user is assigned in Okta, with the roles it offers
+#[derive(Debug)]
+pub struct AwsApplication {
+    pub name: String,
+    pub roles: Vec<Role>,
+}
+
+/// A logged in Okta organization, which can hand out AWS credentials for its profiles
+pub struct Session {
+    organization: Organization,
+    client: OktaClient,
+    interactive: bool,
+    // Read once, rather than for every profile
+    aws_config: Option<ConfigStore>,
+}
+
+impl Session {
+    pub fn organization(&self) -> &Organization {
+        &self.organization
+    }
+
+    pub fn client(&self) -> &OktaClient {
+        &self.client
+    }
+
+    /// Every AWS application of the user, which takes a SAML response per application
+    pub fn aws_applications(&self) -> Result<Vec<AwsApplication>, Error> {
+        self.client
+            .app_links(None)?
+            .into_iter()
+            .filter(|app_link| app_link.app_name == "amazon_aws")
+            .map(|app_link| {
+                let name = app_link.label;
+                let saml = self
+                    .client
+                    .get_saml_response(app_link.link_url)
+                    .with_context(|_| format!("Error getting SAML response for {}", name))?;
+
+                let mut roles = saml.roles.into_iter().collect::<Vec<Role>>();
+                roles.sort_by(|a, b| a.role_arn.cmp(&b.role_arn));
+
+                Ok(AwsApplication { name, roles })
+            })
+            .collect()
+    }
+
+    pub fn credentials(&self, profile: &Profile) -> Result<Credentials, Error> {
+        self.role_credentials(profile)
+            .map(|role_credentials| role_credentials.credentials)
+    }
+
+    /// Credentials for a profile, along with the role they were issued for
+    pub fn role_credentials(&self, profile: &Profile) -> Result<RoleCredentials, Error> {
+        info!(
+            "Requesting tokens for {}/{}",
+            &self.organization.okta_organization.name, profile.name
+        );
+
+        let app_link = self
+            .client
+            .app_links(None)?
+            .into_iter()
+            .find(|app_link| {
+                app_link.app_name == "amazon_aws" && app_link.label == profile.application_name
+            })
+            .ok_or_else(|| {
+                format_err!(
+                    "Could not find Okta application for profile {}/{}",
+                    self.organization.okta_organization.name,
+                    profile.name
+                )
+            })
+            .context(ErrorKind::ApplicationNotFound)?;
+
+        debug!("Application Link: {:?}", &app_link);
+
+        let saml = self
+            .client
+            .get_saml_response(app_link.link_url)
+            .with_context(|_| {
+                format!("Error getting SAML response for profile {}", profile.name)
+            })?;
+
+        trace!("SAML response: {:?}", saml);
+
+        let roles = saml.roles;
+
+        debug!("SAML Roles: {:?}", &roles);
+
+        let role = self.select_role(profile, roles)?;
+
+        trace!(
+            "Found role: {} for profile {}",
+            role.role_arn,
+            &profile.name
+        );
+
+        let partition = role.partition().context(ErrorKind::Saml)?;
+
+        if let Some(destination) = saml.destination.as_ref() {
+            match Partition::from_signin_url(destination) {
+                Some(signin_partition) if signin_partition != partition => warn!(
+                    "SAML response for profile {} is addressed to {}, but its role is in the {} partition",
+                    profile.name, destination, partition
+                ),
+                _ => {}
+            }
+        }
+
+        let mut role_arn = role.role_arn.clone();
+
+        let assumption_response = aws::role::assume_role(
+            role,
+            saml.raw,
+            profile.duration_seconds,
+            sts_region(profile, partition, self.aws_config.as_ref()),
+        )
+        .with_context(|_| format!("Error assuming role for profile {}", profile.name))?;
+
+        let mut credentials = assumption_response
+            .credentials
+            .ok_or_else(|| format_err!("Error fetching credentials from assumed AWS role"))
+            .context(ErrorKind::Sts)?;
+
+        for chained_role in &profile.chain {
+            debug!(
+                "Assuming chained role {} for profile {}",
+                chained_role.role_arn, profile.name
+            );
+
+            let partition =
+                Partition::from_arn(&chained_role.role_arn).context(ErrorKind::Config)?;
+
+            credentials = aws::role::assume_chained_role(
+                credentials,
+                chained_role,
+                &self.organization.username,
+                sts_region(profile, partition, self.aws_config.as_ref()),
+            )
+            .with_context(|_| {
+                format!(
+                    "Error assuming chained role {} for profile {}",
+                    chained_role.role_arn, profile.name
+                )
+            })?;
+            role_arn = chained_role.role_arn.clone();
+        }
+
+        trace!("Credentials: {:?}", credentials);
+
+        Ok(RoleCredentials {
+            role_arn,
+            credentials,
+        })
+    }
+
+    fn select_role(&self, profile: &Profile, roles: HashSet<Role>) -> Result<Role, Error> {
+        let mut roles = roles.into_iter().collect::<Vec<Role>>();
+        roles.sort_by(|a, b| a.role_arn.cmp(&b.role_arn));
+
+        let missing = match profile.role {
+            Some(ref role_name) => {
+                let configured_role = roles
+                    .iter()
+                    .position(|r| r.role_name().map(|r| r == role_name).unwrap_or(false));
+
+                if let Some(index) = configured_role {
+                    return Ok(roles.swap_remove(index));
+                }
+
+                format_err!(
+                    "No matching role ({}) found for profile {}",
+                    role_name,
+                    &profile.name
+                )
+            }
+            None => format_err!("No role specified for profile {}", &profile.name),
+        };
+
+        if !self.interactive {
+            return Err(missing.context(ErrorKind::RoleNotFound).into());
+        }
+
+        warn!("{}, pick one of the available roles", missing);
+
+        let mut menu = Select::new();
+        for role in &roles {
+            menu.item(&format!(
+                "{} / {}",
+                role.account_id().unwrap_or("?"),
+                role.role_name().unwrap_or(role.role_arn.as_str())
+            ));
+        }
+        let role = roles.swap_remove(menu.interact()?);
+
+        let role_name = role.role_name()?.to_owned();
+        let save = Confirmation::new(&format!(
+            "Save {} as the role of {} in {:?}?",
+            role_name, profile.name, self.organization.path
+        ))
+        .interact()?;
+
+        if save {
+            self.organization.save_role(&profile.name, &role_name)?;
+        }
+
+        Ok(role)
+    }
+}
+
+/// Where to assume the role of a profile, using regional STS endpoints.
+///
+/// The region comes from the oktaws config, `~/.aws/config`, `AWS_DEFAULT_REGION` or `AWS_REGION`,
+/// skipping those outside the role's partition, and otherwise is the default region of the partition.
+fn sts_region(profile: &Profile, partition: Partition, aws_config: Option<&ConfigStore>) -> Region {
+    let configured_regions = vec![
+        profile.region().map(|region| region.to_owned()),
+        aws_config
+            .and_then(|config| config.get_region(&profile.name))
+            .map(|region| region.to_owned()),
+        env::var("AWS_DEFAULT_REGION").ok(),
+        env::var("AWS_REGION").ok(),
+    ];
+
+    let region = configured_regions
+        .into_iter()
+        .flatten()
+        .find(|region| {
+            let in_partition = partition.has_region(region);
+            if !in_partition {
+                debug!(
+                    "Skipping region {} for profile {}, as it is not in the {} partition",
+                    region, profile.name, partition
+                );
+            }
+            in_partition
+        })
+        .unwrap_or_else(|| partition.default_region().to_owned());
+
+    let endpoint = profile
+        .sts_endpoint
+        .clone()
+        .unwrap_or_else(|| partition.sts_endpoint(&region));
+
+    Region::Custom {
+        name: region,
+        endpoint,
+    }
+}